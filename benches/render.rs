@@ -0,0 +1,63 @@
+use chip8_emulator::{
+    palette::Palette,
+    rng::Chip8Rng,
+    screen::{Resolution, Screen},
+};
+use rand::{RngCore, SeedableRng};
+use std::{hint::black_box, time::Instant};
+
+// Frames per second turning a Screen into RGBA for the pixels surface, run with `cargo bench`.
+// screen_to_render builds a new Vec every frame that the frontend then copies into the surface,
+// render_into writes into the surface's frame directly.
+const FRAMES: u32 = 20_000;
+
+// A screen at resolution with both planes half filled with noise.
+fn noisy_screen(resolution: Resolution) -> Screen {
+    let mut screen = Screen::new(resolution.width(), resolution.height());
+    screen.set_resolution(resolution);
+    screen.set_palette(Palette::octo());
+    let mut rng = Chip8Rng::seed_from_u64(1);
+    for plane in 0..2 {
+        for y in 0..screen.height() {
+            for x in (0..screen.width()).step_by(8) {
+                screen.draw_sprite(plane, &[rng.next_u32() as u8], x, y);
+            }
+        }
+    }
+    screen
+}
+
+fn time_frames(mut render: impl FnMut()) -> f64 {
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        render();
+    }
+    start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    for resolution in [Resolution::Low, Resolution::High] {
+        let screen = noisy_screen(resolution);
+        let mut frame = vec![0u8; (screen.width() * screen.height() * 4) as usize];
+
+        let copied = time_frames(|| {
+            let colors = screen.screen_to_render();
+            for (pixel, color) in frame.chunks_exact_mut(4).zip(colors.iter()) {
+                pixel.copy_from_slice(color);
+            }
+            black_box(&mut frame);
+        });
+        let direct = time_frames(|| {
+            black_box(&screen).render_into(&mut frame);
+            black_box(&mut frame);
+        });
+        println!(
+            "{:?} {}x{}: screen_to_render + copy {:.0} frames/s, render_into {:.0} frames/s",
+            resolution,
+            screen.width(),
+            screen.height(),
+            FRAMES as f64 / copied,
+            FRAMES as f64 / direct,
+        );
+    }
+}
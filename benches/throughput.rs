@@ -0,0 +1,109 @@
+use chip8_emulator::{
+    asm::assemble,
+    emulator::{Emulator, LOW_RES_HEIGHT, LOW_RES_WIDTH},
+    instruction::Instruction,
+    keyboard::Keyboard,
+    recompiler::Backend,
+    screen::Screen,
+};
+use std::{hint::black_box, time::Instant};
+
+// Instructions per second on roms that never wait, for both backends, run with `cargo bench`.
+// Each loop of the busy rom mixes arithmetic, random numbers, BCD, memory loads, key skips,
+// calls and draws.
+const BUSY_ROM: &str = "
+        LD V5, 1
+loop:
+        ADD V0, 1
+        ADD V1, V0
+        XOR V2, V1
+        SHR V3, V2
+        RND V4, 0xFF
+        LD F, V0
+        DRW V1, V2, 5
+        LD I, scratch
+        LD B, V4
+        LD V2, [I]
+        SKP V5
+        SNE V0, 0
+        CALL add
+        JP loop
+add:
+        ADD V1, V5
+        RET
+scratch:
+        db 0, 0, 0
+";
+
+// Long straight-line register arithmetic, where translating whole blocks pays off most.
+const ARITHMETIC_ROM: &str = "
+loop:
+        ADD V0, 3
+        LD V1, V0
+        SHL V1
+        ADD V2, V1
+        XOR V3, V2
+        SUB V3, V0
+        OR V4, V3
+        AND V4, V2
+        SHR V5, V4
+        ADD V6, V5
+        SUBN V7, V6
+        ADD V7, 0x11
+        LD V8, V7
+        ADD V8, V0
+        XOR V9, V8
+        JP loop
+";
+
+const FRAMES: u32 = 20_000;
+const INSTRUCTIONS_PER_FRAME: u32 = 1_000;
+
+fn run_rom(name: &str, source: &str, backend: Backend) {
+    let rom = assemble(source).expect("benchmark rom assembles");
+    let mut emulator = Emulator::with_seed(0);
+    emulator.set_instructions_per_frame(INSTRUCTIONS_PER_FRAME);
+    emulator.set_backend(backend);
+    emulator.load_rom(rom).expect("benchmark rom fits in memory");
+    let keyboard = Keyboard::new();
+    let mut screen = Screen::new(LOW_RES_WIDTH, LOW_RES_HEIGHT);
+
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        emulator.run_frame(&keyboard, &mut screen).expect("benchmark rom runs");
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let instructions = FRAMES as f64 * INSTRUCTIONS_PER_FRAME as f64;
+    println!(
+        "{} rom, {:?}: {} instructions in {:.3}s, {:.1} million instructions/s",
+        name,
+        backend,
+        instructions,
+        elapsed,
+        instructions / elapsed / 1e6
+    );
+}
+
+fn main() {
+    for backend in [Backend::Interpreter, Backend::Recompiler] {
+        run_rom("busy", BUSY_ROM, backend);
+        run_rom("arithmetic", ARITHMETIC_ROM, backend);
+    }
+
+    // Decoding alone, the if/else chain against the precomputed table.
+    let start = Instant::now();
+    for _ in 0..100 {
+        for opcode in 0..=0xFFFFu16 {
+            black_box(Instruction::parse_opcode(black_box(opcode)));
+        }
+    }
+    let chain = start.elapsed().as_secs_f64();
+    let start = Instant::now();
+    for _ in 0..100 {
+        for opcode in 0..=0xFFFFu16 {
+            black_box(Instruction::lookup(black_box(opcode)));
+        }
+    }
+    let table = start.elapsed().as_secs_f64();
+    println!("decode 6.5M opcodes: parse_opcode {:.3}s, lookup {:.3}s", chain, table);
+}
@@ -0,0 +1,851 @@
+use crate::{
+    emulator::MEMORY_SIZE,
+    instruction::{decode, encode, Address, Byte, DecodedInstruction, Instruction, Nibble, Register},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+// Assembler and disassembler for Chip8, Super Chip and XO-CHIP roms.
+//
+// Source syntax, one statement per line, ';' starts a comment:
+//     label:                  labels name the address of the next byte, roms start at 0x200
+//     NAME = value            constant, usable anywhere a value is
+//     db 0x12, 34, NAME       bytes
+//     dw 0x1234, label        16 bit big endian words
+//     include "file.asm"      assemble another file in place, relative to the including file
+//
+// Values are decimal, 0x hexadecimal or 0b binary numbers, labels or constants, optionally
+// added and subtracted, e.g. `sprites + 5`. Mnemonics and register names are case insensitive.
+//
+// Instructions, Vx/Vy are registers V0-VF, nnn a 12 bit address, kk a byte, n a nibble:
+//     CLS                 00E0        SYS nnn             0nnn
+//     RET                 00EE        JP nnn              1nnn
+//     CALL nnn            2nnn        SE Vx, kk           3xkk
+//     SNE Vx, kk          4xkk        SE Vx, Vy           5xy0
+//     LD Vx, kk           6xkk        ADD Vx, kk          7xkk
+//     LD Vx, Vy           8xy0        OR Vx, Vy           8xy1
+//     AND Vx, Vy          8xy2        XOR Vx, Vy          8xy3
+//     ADD Vx, Vy          8xy4        SUB Vx, Vy          8xy5
+//     SHR Vx {, Vy}       8xy6        SUBN Vx, Vy         8xy7
+//     SHL Vx {, Vy}       8xyE        SNE Vx, Vy          9xy0
+//     LD I, nnn           Annn        JP V0, nnn          Bnnn
+//     RND Vx, kk          Cxkk        DRW Vx, Vy, n       Dxyn
+//     SKP Vx              Ex9E        SKNP Vx             ExA1
+//     LD Vx, DT           Fx07        LD Vx, K            Fx0A
+//     LD DT, Vx           Fx15        LD ST, Vx           Fx18
+//     ADD I, Vx           Fx1E        LD F, Vx            Fx29
+//     LD B, Vx            Fx33        LD [I], Vx          Fx55
+//     LD Vx, [I]          Fx65
+// Super Chip:
+//     SCD n               00Cn        SCR                 00FB
+//     SCL                 00FC        EXIT                00FD
+//     LOW                 00FE        HIGH                00FF
+//     LD HF, Vx           Fx30        LD R, Vx            Fx75
+//     LD Vx, R            Fx85
+// XO-CHIP:
+//     SAVE Vx, Vy         5xy2        LOAD Vx, Vy         5xy3
+//     LD I, LONG nnnn     F000 nnnn   PLANE n             Fn01
+//     AUDIO               F002        PITCH Vx            Fx3A
+// SHR and SHL without Vy use Vx for both, so they behave the same with or without the shift quirk.
+
+pub const ROM_START: usize = 0x200;
+
+// Includes nested deeper than this are assumed to be including themselves.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+// Where a statement came from, for error messages.
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+}
+
+impl Location {
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+enum Statement {
+    Instruction { mnemonic: String, operands: Vec<String> },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    B,
+    HF,
+    R,
+    Long(u32),
+    Value(u32),
+}
+
+const KEYWORDS: [&str; 10] = ["I", "DT", "ST", "K", "F", "B", "HF", "R", "LONG", "[I]"];
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_register(name: &str) -> bool {
+    name.len() == 2 && name.starts_with(['v', 'V']) && name.as_bytes()[1].is_ascii_hexdigit()
+}
+
+fn is_reserved(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    is_register(name) || KEYWORDS.contains(&upper.as_str())
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    let lower = text.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+// Evaluate `term (+|- term)*`, where a term is a number or a symbol.
+fn evaluate(text: &str, symbols: &HashMap<String, u32>) -> Result<u32, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(String::from("Missing value"));
+    }
+    let mut total: i64 = 0;
+    let mut sign = 1;
+    let mut term = String::new();
+    let apply = |term: &str, sign: i64, total: &mut i64| -> Result<(), String> {
+        let term = term.trim();
+        let value = match parse_number(term) {
+            Some(value) => value,
+            None => match symbols.get(term) {
+                Some(value) => *value,
+                None if is_identifier(term) => return Err(format!("Unknown symbol \"{}\"", term)),
+                None => return Err(format!("Invalid value \"{}\"", term)),
+            },
+        };
+        *total += sign * value as i64;
+        Ok(())
+    };
+    for c in text.chars() {
+        if c == '+' || c == '-' {
+            apply(&term, sign, &mut total)?;
+            term.clear();
+            sign = if c == '+' { 1 } else { -1 };
+        } else {
+            term.push(c);
+        }
+    }
+    apply(&term, sign, &mut total)?;
+    u32::try_from(total).map_err(|_| format!("Value of \"{}\" is negative", text))
+}
+
+fn split_operands(text: &str) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    text.split(',').map(|operand| operand.trim().to_string()).collect()
+}
+
+fn parse_operand(text: &str, symbols: &HashMap<String, u32>) -> Result<Operand, String> {
+    if is_register(text) {
+        return Ok(Operand::V(u8::from_str_radix(&text[1..], 16).unwrap()));
+    }
+    let upper = text.to_uppercase();
+    match upper.as_str() {
+        "I" => return Ok(Operand::I),
+        "[I]" => return Ok(Operand::IndirectI),
+        "DT" => return Ok(Operand::DT),
+        "ST" => return Ok(Operand::ST),
+        "K" => return Ok(Operand::K),
+        "F" => return Ok(Operand::F),
+        "B" => return Ok(Operand::B),
+        "HF" => return Ok(Operand::HF),
+        "R" => return Ok(Operand::R),
+        _ => (),
+    }
+    if upper.starts_with("LONG ") {
+        return Ok(Operand::Long(evaluate(&text[5..], symbols)?));
+    }
+    Ok(Operand::Value(evaluate(text, symbols)?))
+}
+
+fn check_range(value: u32, max: u32, what: &str) -> Result<u16, String> {
+    if value > max {
+        return Err(format!("{} {:#x} is larger than {:#x}", what, value, max));
+    }
+    Ok(value as u16)
+}
+
+// Encode one instruction, returns its bytes (2, or 4 for LD I, LONG).
+fn encode_instruction(mnemonic: &str, operands: &[Operand]) -> Result<Vec<u8>, String> {
+    use Instruction::*;
+    use Operand::{IndirectI, Long, Value, B, DT, F, HF, I, K, R, ST, V};
+    let address = |value: u32| Ok::<_, String>(Address(check_range(value, 0xFFF, "Address")?));
+    let byte = |value: u32| Ok::<_, String>(Byte(check_range(value, 0xFF, "Byte")? as u8));
+    let nibble = |value: u32| Ok::<_, String>(Nibble(check_range(value, 0xF, "Nibble")? as u8));
+    let op = DecodedInstruction::new;
+    let vx = |instruction, x: u8| DecodedInstruction { x: Register(x), ..op(instruction) };
+    let vx_vy = |instruction, x: u8, y: u8| DecodedInstruction { y: Register(y), ..vx(instruction, x) };
+    let vx_kk = |instruction, x: u8, kk: Byte| DecodedInstruction { kk, ..vx(instruction, x) };
+    let nnn = |instruction, nnn: Address| DecodedInstruction { nnn, ..op(instruction) };
+    let decoded = match (mnemonic.to_uppercase().as_str(), operands) {
+        ("CLS", []) => op(CLS),
+        ("RET", []) => op(RET),
+        ("SCR", []) => op(SCR),
+        ("SCL", []) => op(SCL),
+        ("EXIT", []) => op(EXIT),
+        ("LOW", []) => op(LOW),
+        ("HIGH", []) => op(HIGH),
+        ("AUDIO", []) => op(AUDIO),
+        ("SCD", [Value(n)]) => DecodedInstruction { n: nibble(*n)?, ..op(SCD_nibble) },
+        ("SYS", [Value(value)]) => nnn(SYS_addr, address(*value)?),
+        ("JP", [Value(value)]) => nnn(JP_addr, address(*value)?),
+        ("JP", [V(0), Value(value)]) => nnn(JP_V0, address(*value)?),
+        ("CALL", [Value(value)]) => nnn(CALL_addr, address(*value)?),
+        ("SE", [V(x), Value(kk)]) => vx_kk(SE_Vx, *x, byte(*kk)?),
+        ("SNE", [V(x), Value(kk)]) => vx_kk(SNE_Vx, *x, byte(*kk)?),
+        ("SE", [V(x), V(y)]) => vx_vy(SE_Vx_Vy, *x, *y),
+        ("SAVE", [V(x), V(y)]) => vx_vy(SAVE_Vx_Vy, *x, *y),
+        ("LOAD", [V(x), V(y)]) => vx_vy(LOAD_Vx_Vy, *x, *y),
+        ("LD", [V(x), Value(kk)]) => vx_kk(LD_Vx, *x, byte(*kk)?),
+        ("ADD", [V(x), Value(kk)]) => vx_kk(ADD_Vx, *x, byte(*kk)?),
+        ("LD", [V(x), V(y)]) => vx_vy(LD_Vx_Vy, *x, *y),
+        ("OR", [V(x), V(y)]) => vx_vy(OR_Vx_Vy, *x, *y),
+        ("AND", [V(x), V(y)]) => vx_vy(AND_Vx_Vy, *x, *y),
+        ("XOR", [V(x), V(y)]) => vx_vy(XOR_Vx_Vy, *x, *y),
+        ("ADD", [V(x), V(y)]) => vx_vy(ADD_Vx_Vy, *x, *y),
+        ("SUB", [V(x), V(y)]) => vx_vy(SUB_Vx_Vy, *x, *y),
+        ("SHR", [V(x)]) => vx_vy(SHR_Vx, *x, *x),
+        ("SHR", [V(x), V(y)]) => vx_vy(SHR_Vx, *x, *y),
+        ("SUBN", [V(x), V(y)]) => vx_vy(SUBN_Vx_Vy, *x, *y),
+        ("SHL", [V(x)]) => vx_vy(SHL_Vx, *x, *x),
+        ("SHL", [V(x), V(y)]) => vx_vy(SHL_Vx, *x, *y),
+        ("SNE", [V(x), V(y)]) => vx_vy(SNE_Vx_Vy, *x, *y),
+        ("LD", [I, Value(value)]) => nnn(LD_I, address(*value)?),
+        ("LD", [I, Long(value)]) => {
+            let long = check_range(*value, 0xFFFF, "Address")?;
+            let opcode = encode(&op(LD_I_long)).unwrap();
+            return Ok([opcode.to_be_bytes(), long.to_be_bytes()].concat());
+        }
+        ("RND", [V(x), Value(kk)]) => vx_kk(RND_Vx, *x, byte(*kk)?),
+        ("DRW", [V(x), V(y), Value(n)]) => {
+            let n = nibble(*n)?;
+            let instruction = if n.0 == 0 { DRW_Vx_Vy_0 } else { DRW_Vx_Vy };
+            DecodedInstruction { n, ..vx_vy(instruction, *x, *y) }
+        }
+        ("SKP", [V(x)]) => vx(SKP_Vx, *x),
+        ("SKNP", [V(x)]) => vx(SKNP_Vx, *x),
+        ("LD", [V(x), DT]) => vx(LD_Vx_DT, *x),
+        ("LD", [V(x), K]) => vx(LD_Vx_K, *x),
+        ("LD", [DT, V(x)]) => vx(LD_DT_Vx, *x),
+        ("LD", [ST, V(x)]) => vx(LD_ST_Vx, *x),
+        ("ADD", [I, V(x)]) => vx(ADD_I_Vx, *x),
+        ("LD", [F, V(x)]) => vx(LD_F_Vx, *x),
+        ("LD", [HF, V(x)]) => vx(LD_HF_Vx, *x),
+        ("LD", [B, V(x)]) => vx(LD_B_Vx, *x),
+        ("PITCH", [V(x)]) => vx(PITCH_Vx, *x),
+        ("LD", [IndirectI, V(x)]) => vx(LD_I_Vx, *x),
+        ("LD", [V(x), IndirectI]) => vx(LD_Vx_I, *x),
+        ("LD", [R, V(x)]) => vx(LD_R_Vx, *x),
+        ("LD", [V(x), R]) => vx(LD_Vx_R, *x),
+        // the plane mask is stored where x normally is
+        ("PLANE", [Value(n)]) => vx(PLANE_nibble, nibble(*n)?.0),
+        _ => return Err(format!("Invalid operands for \"{}\"", mnemonic)),
+    };
+    Ok(encode(&decoded).unwrap().to_be_bytes().to_vec())
+}
+
+// Size in bytes of a statement, known before any symbol is resolved so labels can be placed in one pass.
+fn statement_size(statement: &Statement) -> usize {
+    match statement {
+        Statement::Instruction { mnemonic, operands } => {
+            let long = operands
+                .get(1)
+                .is_some_and(|operand| operand.to_uppercase().starts_with("LONG "));
+            if mnemonic.eq_ignore_ascii_case("LD") && long {
+                4
+            } else {
+                2
+            }
+        }
+        Statement::Bytes(values) => values.len(),
+        Statement::Words(values) => values.len() * 2,
+    }
+}
+
+struct Assembler<'a> {
+    load: &'a dyn Fn(&Path) -> Result<String, String>,
+    symbols: HashMap<String, u32>,
+    statements: Vec<(Location, Statement)>,
+    address: usize,
+}
+
+impl<'a> Assembler<'a> {
+    // First pass: expand includes, define labels and constants and size every statement.
+    fn read(&mut self, source: &str, path: &Path, depth: usize) -> Result<(), AsmError> {
+        for (index, raw_line) in source.lines().enumerate() {
+            let location = Location {
+                file: path.display().to_string(),
+                line: index + 1,
+            };
+            let mut line = raw_line.split(';').next().unwrap().trim();
+
+            // any number of labels can start a line
+            while let Some(colon) = line.find(':') {
+                let label = line[..colon].trim();
+                if !is_identifier(label) {
+                    break;
+                }
+                self.define(&location, label, (ROM_START + self.address) as u32)?;
+                line = line[colon + 1..].trim();
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            let (word, rest) = match line.find(char::is_whitespace) {
+                Some(space) => (&line[..space], line[space..].trim()),
+                None => (line, ""),
+            };
+            if let Some(value) = rest.strip_prefix('=') {
+                if !is_identifier(word) {
+                    return Err(location.error(format!("Invalid constant name \"{}\"", word)));
+                }
+                let value = evaluate(value, &self.symbols).map_err(|message| location.error(message))?;
+                self.define(&location, word, value)?;
+                continue;
+            }
+
+            let statement = match word.to_lowercase().as_str() {
+                "include" => {
+                    let name = rest
+                        .strip_prefix('"')
+                        .and_then(|rest| rest.strip_suffix('"'))
+                        .ok_or_else(|| location.error(String::from("include needs a quoted filename")))?;
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(location.error(String::from("Includes nested too deeply")));
+                    }
+                    let include_path = match path.parent() {
+                        Some(parent) => parent.join(name),
+                        None => PathBuf::from(name),
+                    };
+                    let included = (self.load)(&include_path).map_err(|message| location.error(message))?;
+                    self.read(&included, &include_path, depth + 1)?;
+                    continue;
+                }
+                "db" => Statement::Bytes(split_operands(rest)),
+                "dw" => Statement::Words(split_operands(rest)),
+                _ => Statement::Instruction {
+                    mnemonic: word.to_string(),
+                    operands: split_operands(rest),
+                },
+            };
+            self.address += statement_size(&statement);
+            self.statements.push((location, statement));
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, location: &Location, name: &str, value: u32) -> Result<(), AsmError> {
+        if is_reserved(name) {
+            return Err(location.error(format!("\"{}\" is a reserved name", name)));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(location.error(format!("\"{}\" is defined more than once", name)));
+        }
+        Ok(())
+    }
+
+    // Second pass: every symbol is known, encode the statements.
+    fn write(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::with_capacity(self.address);
+        for (location, statement) in self.statements.iter() {
+            let error = |message: String| location.error(message);
+            match statement {
+                Statement::Instruction { mnemonic, operands } => {
+                    let operands = operands
+                        .iter()
+                        .map(|operand| parse_operand(operand, &self.symbols))
+                        .collect::<Result<Vec<Operand>, String>>()
+                        .map_err(error)?;
+                    let bytes = encode_instruction(mnemonic, &operands).map_err(error)?;
+                    rom.extend_from_slice(&bytes);
+                }
+                Statement::Bytes(values) => {
+                    for value in values {
+                        let value = evaluate(value, &self.symbols).map_err(error)?;
+                        rom.push(check_range(value, 0xFF, "Byte").map_err(error)? as u8);
+                    }
+                }
+                Statement::Words(values) => {
+                    for value in values {
+                        let value = evaluate(value, &self.symbols).map_err(error)?;
+                        let word = check_range(value, 0xFFFF, "Word").map_err(error)?;
+                        rom.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+            }
+        }
+        if rom.len() > MEMORY_SIZE - ROM_START {
+            return Err(AsmError {
+                file: String::new(),
+                line: 0,
+                message: format!("Rom is {} bytes, larger than memory", rom.len()),
+            });
+        }
+        Ok(rom)
+    }
+}
+
+// Assemble source read from path, includes are loaded with load.
+pub fn assemble_with(
+    source: &str,
+    path: &Path,
+    load: &dyn Fn(&Path) -> Result<String, String>,
+) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler {
+        load,
+        symbols: HashMap::new(),
+        statements: Vec::new(),
+        address: 0,
+    };
+    assembler.read(source, path, 0)?;
+    assembler.write()
+}
+
+// Assemble source that has no includes.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with(source, Path::new("<source>"), &|path: &Path| {
+        Err(format!("Can not include {} here", path.display()))
+    })
+}
+
+// Assemble a file from disk, includes are read relative to it.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let load = |path: &Path| {
+        fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))
+    };
+    let source = load(path).map_err(|message| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        message,
+    })?;
+    assemble_with(&source, path, &load)
+}
+
+fn opcode_at(rom: &[u8], offset: usize) -> Option<u16> {
+    if offset + 1 < rom.len() {
+        Some(((rom[offset] as u16) << 8) | rom[offset + 1] as u16)
+    } else {
+        None
+    }
+}
+
+// Length of the instruction at offset, None if the bytes there can not be an instruction.
+fn instruction_length(rom: &[u8], offset: usize) -> Option<usize> {
+    match Instruction::parse_opcode(opcode_at(rom, offset)?) {
+        Instruction::Invalid_Instruction => None,
+        Instruction::LD_I_long if offset + 3 >= rom.len() => None,
+        Instruction::LD_I_long => Some(4),
+        _ => Some(2),
+    }
+}
+
+// Text for the instruction at offset, addresses that have a label are written as the label.
+fn format_instruction(rom: &[u8], offset: usize, labels: &HashSet<usize>) -> String {
+    let decoded = decode(opcode_at(rom, offset).unwrap());
+    let x = decoded.x.0;
+    let y = decoded.y.0;
+    let n = decoded.n.0;
+    let kk = decoded.kk.0;
+    let address = |address: usize| {
+        if labels.contains(&address) {
+            label_name(address)
+        } else {
+            format!("{:#05x}", address)
+        }
+    };
+    let nnn = address(decoded.nnn.0 as usize);
+    match decoded.instruction {
+        Instruction::SYS_addr => format!("SYS {}", nnn),
+        Instruction::CLS => String::from("CLS"),
+        Instruction::RET => String::from("RET"),
+        Instruction::JP_addr => format!("JP {}", nnn),
+        Instruction::CALL_addr => format!("CALL {}", nnn),
+        Instruction::SE_Vx => format!("SE V{:X}, {:#04x}", x, kk),
+        Instruction::SNE_Vx => format!("SNE V{:X}, {:#04x}", x, kk),
+        Instruction::SE_Vx_Vy => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::LD_Vx => format!("LD V{:X}, {:#04x}", x, kk),
+        Instruction::ADD_Vx => format!("ADD V{:X}, {:#04x}", x, kk),
+        Instruction::LD_Vx_Vy => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::OR_Vx_Vy => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::AND_Vx_Vy => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::XOR_Vx_Vy => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::ADD_Vx_Vy => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::SUB_Vx_Vy => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::SHR_Vx => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::SUBN_Vx_Vy => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::SHL_Vx => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SNE_Vx_Vy => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LD_I => format!("LD I, {}", nnn),
+        Instruction::JP_V0 => format!("JP V0, {}", nnn),
+        Instruction::RND_Vx => format!("RND V{:X}, {:#04x}", x, kk),
+        Instruction::DRW_Vx_Vy | Instruction::DRW_Vx_Vy_0 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::SKP_Vx => format!("SKP V{:X}", x),
+        Instruction::SKNP_Vx => format!("SKNP V{:X}", x),
+        Instruction::LD_Vx_DT => format!("LD V{:X}, DT", x),
+        Instruction::LD_Vx_K => format!("LD V{:X}, K", x),
+        Instruction::LD_DT_Vx => format!("LD DT, V{:X}", x),
+        Instruction::LD_ST_Vx => format!("LD ST, V{:X}", x),
+        Instruction::ADD_I_Vx => format!("ADD I, V{:X}", x),
+        Instruction::LD_F_Vx => format!("LD F, V{:X}", x),
+        Instruction::LD_B_Vx => format!("LD B, V{:X}", x),
+        Instruction::LD_I_Vx => format!("LD [I], V{:X}", x),
+        Instruction::LD_Vx_I => format!("LD V{:X}, [I]", x),
+        Instruction::SCD_nibble => format!("SCD {}", n),
+        Instruction::SCR => String::from("SCR"),
+        Instruction::SCL => String::from("SCL"),
+        Instruction::EXIT => String::from("EXIT"),
+        Instruction::LOW => String::from("LOW"),
+        Instruction::HIGH => String::from("HIGH"),
+        Instruction::LD_HF_Vx => format!("LD HF, V{:X}", x),
+        Instruction::LD_R_Vx => format!("LD R, V{:X}", x),
+        Instruction::LD_Vx_R => format!("LD V{:X}, R", x),
+        Instruction::LD_I_long => {
+            let long = opcode_at(rom, offset + 2).unwrap() as usize;
+            let long = if labels.contains(&long) { label_name(long) } else { format!("{:#06x}", long) };
+            format!("LD I, LONG {}", long)
+        }
+        Instruction::PLANE_nibble => format!("PLANE {}", x),
+        Instruction::AUDIO => String::from("AUDIO"),
+        Instruction::PITCH_Vx => format!("PITCH V{:X}", x),
+        Instruction::SAVE_Vx_Vy => format!("SAVE V{:X}, V{:X}", x, y),
+        Instruction::LOAD_Vx_Vy => format!("LOAD V{:X}, V{:X}", x, y),
+        Instruction::Invalid_Instruction => unreachable!("invalid instructions are disassembled as data"),
+    }
+}
+
+fn label_name(address: usize) -> String {
+    format!("L{:03X}", address)
+}
+
+// Follow every path the program can take from the start of the rom. Returns the offsets where
+// instructions start, and every address the code jumps to or points I at.
+fn trace(rom: &[u8]) -> (Vec<bool>, HashSet<usize>) {
+    let mut code = vec![false; rom.len()];
+    let mut targets = HashSet::new();
+    let mut pending = vec![0usize];
+    let offset_of = |address: usize| address.checked_sub(ROM_START).filter(|offset| *offset < rom.len());
+
+    while let Some(offset) = pending.pop() {
+        if offset >= rom.len() || code[offset] {
+            continue;
+        }
+        let length = match instruction_length(rom, offset) {
+            Some(length) => length,
+            None => continue,
+        };
+        code[offset] = true;
+        let decoded = decode(opcode_at(rom, offset).unwrap());
+        let nnn = decoded.nnn.0 as usize;
+        let next = offset + length;
+        match decoded.instruction {
+            Instruction::JP_addr => {
+                targets.insert(nnn);
+                pending.extend(offset_of(nnn));
+            }
+            Instruction::CALL_addr => {
+                targets.insert(nnn);
+                pending.extend(offset_of(nnn));
+                pending.push(next);
+            }
+            Instruction::SE_Vx
+            | Instruction::SNE_Vx
+            | Instruction::SE_Vx_Vy
+            | Instruction::SNE_Vx_Vy
+            | Instruction::SKP_Vx
+            | Instruction::SKNP_Vx => {
+                pending.push(next);
+                pending.push(next + instruction_length(rom, next).unwrap_or(2));
+            }
+            Instruction::LD_I => {
+                targets.insert(nnn);
+                pending.push(next);
+            }
+            Instruction::LD_I_long => {
+                targets.insert(opcode_at(rom, offset + 2).unwrap() as usize);
+                pending.push(next);
+            }
+            // Nothing follows these that the disassembler can know about. SYS routines were machine
+            // code on the original hardware and are usually padding in roms.
+            Instruction::RET | Instruction::EXIT | Instruction::JP_V0 | Instruction::SYS_addr => (),
+            _ => pending.push(next),
+        }
+    }
+    (code, targets)
+}
+
+enum Line {
+    Instruction(usize, usize),
+    Word(usize),
+    Data(usize, usize),
+}
+
+// Disassemble a rom into source that assemble() turns back into exactly the same bytes.
+// Bytes only reachable as code are written as instructions, everything else as db data.
+// Instructions whose encoding has bits the mnemonic can not express (e.g. 00E1) are written with dw.
+pub fn disassemble(rom: &[u8]) -> String {
+    let (code, targets) = trace(rom);
+
+    // Split the rom into lines, a data line stops at the next instruction or jump target.
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        if code[offset] {
+            let length = instruction_length(rom, offset).unwrap();
+            lines.push(Line::Instruction(offset, length));
+            offset += length;
+            continue;
+        }
+        let start = offset;
+        offset += 1;
+        while offset < rom.len()
+            && offset - start < 8
+            && !code[offset]
+            && !targets.contains(&(offset + ROM_START))
+        {
+            offset += 1;
+        }
+        lines.push(Line::Data(start, offset));
+    }
+
+    // Only addresses that start a line can be labelled.
+    let starts: HashSet<usize> = lines
+        .iter()
+        .map(|line| match line {
+            Line::Instruction(start, _) | Line::Word(start) | Line::Data(start, _) => start + ROM_START,
+        })
+        .collect();
+    let labels: HashSet<usize> = targets.intersection(&starts).cloned().collect();
+    let symbols: HashMap<String, u32> = labels
+        .iter()
+        .map(|address| (label_name(*address), *address as u32))
+        .collect();
+
+    // Write instructions that would not assemble back to the same bytes as words.
+    for line in lines.iter_mut() {
+        if let Line::Instruction(start, length) = *line {
+            let text = format_instruction(rom, start, &labels);
+            if reassemble(&text, &symbols).as_deref() != Some(&rom[start..start + length]) {
+                *line = if length == 2 { Line::Word(start) } else { Line::Data(start, start + length) };
+            }
+        }
+    }
+
+    let mut output = String::from("; Disassembled by chip8-disasm, assemble with chip8-asm\n");
+    for line in lines {
+        let (start, text, end) = match line {
+            Line::Instruction(start, length) => (start, format_instruction(rom, start, &labels), start + length),
+            Line::Word(start) => (start, format!("dw {:#06x}", opcode_at(rom, start).unwrap()), start + 2),
+            Line::Data(start, end) => {
+                let bytes: Vec<String> = rom[start..end].iter().map(|byte| format!("{:#04x}", byte)).collect();
+                (start, format!("db {}", bytes.join(", ")), end)
+            }
+        };
+        if labels.contains(&(start + ROM_START)) {
+            output.push_str(&format!("{}:\n", label_name(start + ROM_START)));
+        }
+        let bytes: Vec<String> = rom[start..end].iter().map(|byte| format!("{:02X}", byte)).collect();
+        output.push_str(&format!(
+            "    {:<40}; {:#05x}: {}\n",
+            text,
+            start + ROM_START,
+            bytes.join(" ")
+        ));
+    }
+    output
+}
+
+fn reassemble(text: &str, symbols: &HashMap<String, u32>) -> Option<Vec<u8>> {
+    let (mnemonic, rest) = match text.find(' ') {
+        Some(space) => (&text[..space], &text[space..]),
+        None => (text, ""),
+    };
+    let operands = split_operands(rest)
+        .iter()
+        .map(|operand| parse_operand(operand, symbols))
+        .collect::<Result<Vec<Operand>, String>>()
+        .ok()?;
+    encode_instruction(mnemonic, &operands).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble_instructions() {
+        let source = "
+            start:  CLS             ; clear
+                    LD V1, 0x10
+                    ld va, vb
+                    DRW V1, V2, 5
+                    LD [I], VF
+                    LD I, LONG 0x1234
+                    SHR V3
+                    PLANE 2
+                    JP start
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![
+                0x00, 0xE0, 0x61, 0x10, 0x8A, 0xB0, 0xD1, 0x25, 0xFF, 0x55, 0xF0, 0x00, 0x12, 0x34, 0x83,
+                0x36, 0xF2, 0x01, 0x12, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = "
+            SPEED = 3
+            LD I, sprite
+            ADD V0, SPEED + 1
+            JP end
+            sprite: db 0xFF, 0b10000001, 255
+                    dw end, 0x1234
+            end:    JP end
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0xA2, 0x06, 0x70, 0x04, 0x12, 0x0D, 0xFF, 0x81, 0xFF, 0x02, 0x0D, 0x12, 0x34, 0x12, 0x0D]
+        );
+    }
+
+    #[test]
+    fn includes() {
+        let load = |path: &Path| match path.to_str() {
+            Some("dir/sprites.asm") => Ok(String::from("sprite: db 0x3C\ninclude \"more.asm\"")),
+            Some("dir/more.asm") => Ok(String::from("db 0x7E")),
+            Some("dir/self.asm") => Ok(String::from("include \"self.asm\"")),
+            _ => Err(String::from("missing")),
+        };
+        let rom = assemble_with("LD I, sprite\ninclude \"sprites.asm\"", Path::new("dir/main.asm"), &load).unwrap();
+        assert_eq!(rom, vec![0xA2, 0x02, 0x3C, 0x7E]);
+        assert!(assemble_with("include \"self.asm\"", Path::new("dir/main.asm"), &load).is_err());
+        assert!(assemble_with("include \"gone.asm\"", Path::new("dir/main.asm"), &load).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let error = assemble("CLS\nLD V1, 0x100").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(assemble("JP nowhere").is_err());
+        assert!(assemble("LD V1").is_err());
+        assert!(assemble("FOO V1, V2").is_err());
+        assert!(assemble("DRW V1, V2, 16").is_err());
+        assert!(assemble("a: CLS\na: CLS").is_err());
+        assert!(assemble("V1 = 3").is_err());
+    }
+
+    #[test]
+    fn disassemble_code_and_data() {
+        let rom = vec![0xA2, 0x06, 0xD0, 0x15, 0x12, 0x04, 0xF0, 0x90, 0xF0];
+        let source = disassemble(&rom);
+        assert!(source.contains("LD I, L206"));
+        assert!(source.contains("L204:\n    JP L204"));
+        assert!(source.contains("db 0xf0, 0x90, 0xf0"));
+        assert_eq!(assemble(&source).unwrap(), rom);
+    }
+
+    #[test]
+    fn round_trip_is_byte_identical() {
+        let mut rom: Vec<u8> = Vec::new();
+        // every instruction form, then non canonical encodings and an odd trailing byte
+        let program = "
+            CLS
+            SE V1, 2
+            SKP V2
+            LD I, LONG data
+            CALL sub
+            SCD 4
+            SCR
+            SCL
+            LOW
+            HIGH
+            LD HF, V1
+            LD R, V2
+            LD V3, R
+            SAVE V1, V2
+            LOAD V3, V4
+            PLANE 3
+            AUDIO
+            PITCH V5
+            SHL V1, V2
+            SUBN V3, V4
+            DRW V1, V2, 0
+            RND V1, 0xFF
+            LD V1, K
+            LD V1, DT
+            LD DT, V1
+            LD ST, V1
+            ADD I, V1
+            LD F, V1
+            LD B, V1
+            LD V1, [I]
+            SNE V1, V2
+            SE V1, V2
+            JP V0, data
+            sub:
+            dw 0x00E1
+            RET
+            data: db 0xAA
+        ";
+        rom.extend(assemble(program).unwrap());
+        assert_eq!(assemble(&disassemble(&rom)).unwrap(), rom);
+
+        // every possible word as the whole rom, reachable from the start
+        for opcode in 0..=0xFFFFu32 {
+            let rom = vec![(opcode >> 8) as u8, opcode as u8, 0x12, 0x00, 0x00];
+            assert_eq!(assemble(&disassemble(&rom)).unwrap(), rom, "{:#06x}", opcode);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use chip8_emulator::asm::assemble_file;
+use std::{env, fs, path::Path, process::exit};
+
+// Assemble a source file into a rom, see src/asm.rs for the syntax.
+//     chip8-asm <source.asm> <rom.ch8>
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        println!("Usage: chip8-asm <source.asm> <rom.ch8>");
+        exit(-1);
+    }
+
+    let rom = match assemble_file(Path::new(&args[1])) {
+        Ok(rom) => rom,
+        Err(err) => {
+            println!("{}", err);
+            exit(1);
+        }
+    };
+    if let Err(err) = fs::write(&args[2], &rom) {
+        println!("Could not write {}: {}", args[2], err);
+        exit(1);
+    }
+}
@@ -0,0 +1,31 @@
+use chip8_emulator::asm::disassemble;
+use std::{env, fs, process::exit};
+
+// Disassemble a rom into source that chip8-asm turns back into the same rom.
+//     chip8-disasm <rom.ch8> [<source.asm>]
+// The source is printed when no destination is given.
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 && args.len() != 3 {
+        println!("Usage: chip8-disasm <rom.ch8> [<source.asm>]");
+        exit(-1);
+    }
+
+    let rom = match fs::read(&args[1]) {
+        Ok(rom) => rom,
+        Err(_) => {
+            println!("ROM not found");
+            exit(1);
+        }
+    };
+    let source = disassemble(&rom);
+    match args.get(2) {
+        Some(destination) => {
+            if let Err(err) = fs::write(destination, source) {
+                println!("Could not write {}: {}", destination, err);
+                exit(1);
+            }
+        }
+        None => print!("{}", source),
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+
+use crate::{palette::Palette, screen::Screen};
+
+// Frames blended together when no count is given, enough for roms that erase and redraw
+// every other frame.
+pub const DEFAULT_BLEND_FRAMES: usize = 2;
+
+// A cheaper way than the phosphor filter to hide XOR flicker: every pixel is shown as the OR
+// of its colour index over the last few frames, so a sprite that is only on screen every
+// other frame stays visible. Only what is shown changes, the Screen itself is left alone.
+#[derive(Debug, Clone)]
+pub struct FrameBlend {
+    frames: usize,
+    width: u32,
+    height: u32,
+    // Colour indices of the last frames, oldest first.
+    history: VecDeque<Vec<u8>>,
+    // Colour index of every pixel as it is shown.
+    blended: Vec<u8>,
+    palette: Palette,
+}
+
+impl FrameBlend {
+    pub fn new(frames: usize) -> FrameBlend {
+        let frames = frames.max(1);
+        FrameBlend {
+            frames,
+            width: 0,
+            height: 0,
+            history: VecDeque::with_capacity(frames),
+            blended: Vec::new(),
+            palette: Palette::default(),
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    // Forget the earlier frames, the next update shows the screen as it is.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.blended.clear();
+    }
+
+    // Add the screen as the newest frame, dropping the oldest. Returns true if what is shown changed.
+    pub fn update(&mut self, screen: &Screen) -> bool {
+        if (screen.width(), screen.height()) != (self.width, self.height) {
+            self.width = screen.width();
+            self.height = screen.height();
+            self.reset();
+        }
+        // reuse the oldest frame's buffer for the newest one
+        let mut frame = if self.history.len() == self.frames {
+            self.history.pop_front().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        frame.clear();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                frame.push(screen.color_index(x, y) as u8);
+            }
+        }
+        self.history.push_back(frame);
+
+        let len = (self.width * self.height) as usize;
+        let mut changed = self.palette != screen.palette() || self.blended.len() != len;
+        self.palette = screen.palette();
+        self.blended.resize(len, 0);
+        for (pos, shown) in self.blended.iter_mut().enumerate() {
+            let index = self.history.iter().fold(0, |index, frame| index | frame[pos]);
+            if *shown != index {
+                *shown = index;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // Write the blended image as RGBA into buffer, the same layout as Screen::render_into.
+    pub fn render_into(&self, buffer: &mut [u8]) {
+        for (pixel, index) in buffer.chunks_exact_mut(4).zip(&self.blended) {
+            pixel.copy_from_slice(&self.palette.colors[*index as usize]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered(blend: &FrameBlend) -> Vec<[u8; 4]> {
+        let mut buffer = vec![0; blend.blended.len() * 4];
+        blend.render_into(&mut buffer);
+        buffer.chunks_exact(4).map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]]).collect()
+    }
+
+    #[test]
+    fn flickering_pixel_stays_on() {
+        let mut s = Screen::new(2, 1);
+        let on = s.palette().colors[1];
+        let off = s.palette().colors[0];
+        let mut blend = FrameBlend::new(2);
+        s.set_pixel(0, 0);
+        assert!(blend.update(&s));
+        assert_eq!(rendered(&blend), vec![on, off]);
+
+        // erased and drawn again every other frame, the pixel never goes dark
+        for _ in 0..6 {
+            s.set_pixel(0, 0);
+            assert!(!blend.update(&s));
+            assert_eq!(rendered(&blend), vec![on, off]);
+        }
+
+        // off for two frames in a row it disappears
+        s.set_pixel(0, 0);
+        blend.update(&s);
+        assert!(blend.update(&s));
+        assert_eq!(rendered(&blend), vec![off, off]);
+    }
+
+    #[test]
+    fn planes_are_combined() {
+        let mut s = Screen::new(1, 1);
+        let mut blend = FrameBlend::new(3);
+        s.set_pixel(0, 0);
+        blend.update(&s);
+        s.set_pixel(0, 0);
+        s.set_plane_mask(2);
+        s.set_pixel(0, 0);
+        blend.update(&s);
+        assert_eq!(blend.blended, vec![3]);
+        s.set_palette(Palette::amber());
+        assert!(blend.update(&s));
+        assert_eq!(rendered(&blend), vec![Palette::amber().colors[3]]);
+    }
+
+    #[test]
+    fn single_frame_and_reset() {
+        let mut s = Screen::new(1, 1);
+        let mut blend = FrameBlend::new(0);
+        assert_eq!(blend.frames(), 1);
+        s.set_pixel(0, 0);
+        blend.update(&s);
+        s.set_pixel(0, 0);
+        assert!(blend.update(&s));
+        assert_eq!(blend.blended, vec![0]);
+
+        let mut blend = FrameBlend::new(4);
+        s.set_pixel(0, 0);
+        blend.update(&s);
+        s.set_pixel(0, 0);
+        blend.reset();
+        assert!(blend.update(&s));
+        assert_eq!(blend.blended, vec![0]);
+    }
+}
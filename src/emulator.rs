@@ -1,9 +1,51 @@
-use crate::{instruction::Instruction, keyboard::Keyboard, screen::Screen};
-use rand::Rng;
+use crate::{
+    error::EmulatorError,
+    instruction::{decode, Address, Byte, DecodedInstruction, Instruction, Nibble, Register},
+    keyboard::Keyboard,
+    quirks::Quirks,
+    recompiler::{translate_block, Backend, BlockCache, MicroOp},
+    rng::Chip8Rng,
+    save_state::{SaveStateError, StateReader, StateWriter},
+    screen::{Resolution, Screen, SpriteReport},
+};
+use rand::{Rng, SeedableRng};
 use std::fmt;
 
 
-use chrono::{Local, DateTime};
+// Super Chip 10 byte high resolution font for the digits 0-F, loaded into memory
+// directly after the standard 5 byte font.
+const LARGE_CHARACTER_SPRITES: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFE, 0xFF, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xC3, 0xFF, 0xFE, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// Screen sizes for the Super Chip LOW and HIGH instructions.
+pub const LOW_RES_WIDTH: u32 = 64;
+pub const LOW_RES_HEIGHT: u32 = 32;
+pub const HIGH_RES_WIDTH: u32 = 128;
+pub const HIGH_RES_HEIGHT: u32 = 64;
+
+// XO-CHIP extends the address space to 64KiB, the original 4KiB programs still load at 0x200.
+pub const MEMORY_SIZE: usize = 0x10000;
+
+// Since chip8 was originally an interpreted language, there is no offical rate that
+// it runs at. I found 540hz from the internet to be a good rate, which is 9 instructions
+// for every 60Hz timer tick.
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 9;
 
 // #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
@@ -17,20 +59,43 @@ pub struct Emulator {
     delay_timer_register: u8,
     sound_timer_register: u8,
     address_register: u16,
-    memory: [u8; 0x1000],
+    memory: [u8; MEMORY_SIZE],
     program_memory_index: usize,
     // display_refresh_memory_index: usize,
     // eti_660_memory_index: usize,
     sprite_memory_index: usize,
+    large_sprite_memory_index: usize,
     stack: [u16; 16],
-    time_counter: chrono::NaiveTime,
+    // Super Chip RPL user flags, saved and loaded with FX75 and FX85
+    rpl_flags: [u8; 16],
+    // XO-CHIP 128 bit audio pattern loaded with F002, and the playback pitch set with FX3A
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    instructions_per_frame: u32,
+    quirks: Quirks,
+    // RND_Vx source, reseeded from seed whenever a rom is loaded so runs can be replayed
+    seed: u64,
+    rng: Chip8Rng,
     paused: bool,
+    exited: bool,
+    // The opcode and decoded instruction at each address, filled in as the pc reaches it and
+    // cleared for the addresses a memory write touches. Empty until the first step.
+    decode_cache: Vec<Option<(u16, DecodedInstruction)>>,
+    // How run_frame executes instructions, and the blocks translated for the Recompiler backend.
+    backend: Backend,
+    blocks: BlockCache,
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 impl Emulator {
     #[cfg_attr(target_arch = "wasm32", wasm_bindgen(constructor))]
     pub fn new() -> Emulator {
+        Emulator::with_seed(rand::thread_rng().gen())
+    }
+
+    // Create an emulator whose RND_Vx results are fully determined by seed. Two emulators with the
+    // same seed, rom and input produce the same frames.
+    pub fn with_seed(seed: u64) -> Emulator {
         let mut e = Emulator {
             registers: [0; 16],
             flag_register_index: 0xF,
@@ -39,14 +104,25 @@ impl Emulator {
             delay_timer_register: 0,
             sound_timer_register: 0,
             address_register: 0,
-            memory: [0; 0x1000],
+            memory: [0; MEMORY_SIZE],
             program_memory_index: 0x200,
             // display_refresh_memory_index: 0xF00,
             // eti_660_memory_index: 0x600,
             sprite_memory_index: 0x000,
+            large_sprite_memory_index: 0x050,
             stack: [0; 16],
-            time_counter: Local::now().time(),
+            rpl_flags: [0; 16],
+            audio_pattern: [0; 16],
+            pitch: 64,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            quirks: Quirks::default(),
+            seed,
+            rng: Chip8Rng::seed_from_u64(seed),
             paused: false,
+            exited: false,
+            decode_cache: Vec::new(),
+            backend: Backend::Interpreter,
+            blocks: BlockCache::default(),
         };
         e.set_character_sprites();
         e
@@ -150,6 +226,10 @@ impl Emulator {
         self.memory[self.sprite_memory_index + 77] = 0xF0;
         self.memory[self.sprite_memory_index + 78] = 0x80;
         self.memory[self.sprite_memory_index + 79] = 0x80;
+        // Super Chip large 0-F
+        self.memory[self.large_sprite_memory_index
+            ..self.large_sprite_memory_index + LARGE_CHARACTER_SPRITES.len()]
+            .copy_from_slice(&LARGE_CHARACTER_SPRITES);
     }
 
     fn get_nonzero_memory(&self) -> u16 {
@@ -182,6 +262,7 @@ impl Emulator {
 
     pub fn set_memory(&mut self, byte: u8, mem_address: usize) {
         self.memory[mem_address] = byte;
+        self.invalidate_decode_cache(mem_address, 1);
     }
 
 
@@ -192,9 +273,14 @@ impl Emulator {
         self.delay_timer_register = 0;
         self.sound_timer_register = 0;
         self.address_register = 0;
-        self.memory = [0; 0x1000];
+        self.memory = [0; MEMORY_SIZE];
+        self.decode_cache.clear();
+        self.blocks.clear();
         self.stack = [0; 16];
-        self.time_counter = Local::now().time();
+        self.exited = false;
+        self.rng = Chip8Rng::seed_from_u64(self.seed);
+        self.audio_pattern = [0; 16];
+        self.pitch = 64;
         self.set_character_sprites();
     }
 
@@ -207,32 +293,26 @@ impl Emulator {
         }
     }
 
-    // Both delay and sound timer in the Chip8 decrement at a rate of 60Hz. Since nothing outside of the emulator
-    // reads these values, it is safe to check how much time has passed since last emulation step and decrement accordingly
-    // This is an impresise method but should be good enough for this project.
+    // Both delay and sound timer in the Chip8 decrement at a rate of 60Hz, which is once per frame.
     fn decrement_counters(&mut self) {
-        let now = chrono::Local::now().time();
-        let diff = now - self.time_counter;
-        let delay = diff.num_milliseconds();
-        if delay < 17 {
-            return;
-        }
-        if self.delay_timer_register > 0 {
-            if delay >= self.delay_timer_register.into() {
-                self.delay_timer_register = 0;
-            } else {
-                self.delay_timer_register -= (delay / 17) as u8;
-            }
-        }
-        if self.sound_timer_register > 0 {
-            if delay >= self.sound_timer_register.into() {
-                self.sound_timer_register = 0;
-            } else {
-                self.sound_timer_register -= (delay / 17) as u8;
-            }
-        }
+        self.delay_timer_register = self.delay_timer_register.saturating_sub(1);
+        self.sound_timer_register = self.sound_timer_register.saturating_sub(1);
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer_register
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer_register
+    }
 
-        self.time_counter = Local::now().time();
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame;
     }
 
     pub fn pause(&mut self) {
@@ -243,394 +323,828 @@ impl Emulator {
         self.paused = false;
     }
 
-    // This function handles all "External" aspects of opcode, i.e. updating timers,
-    // waiting for input, incrememting pc or not, running multiple steps, etc.
-    // Since chip8 was originally an interpreted language, there is no offical rate that
-    // it runs at. I found 540hz from the internet to be a good rate.
-    pub fn emulate_step(&mut self, keyboard: &Keyboard, screen: &mut Screen, diff_as_microseconds: i64) {
-        if self.paused { return; }
-        if diff_as_microseconds < 1851 { return; }
-        let steps = diff_as_microseconds / 1851;
-
-        for i in 0..steps {
-            self.decrement_counters();
-            let first_byte = self.memory[self.pc as usize];
-            let second_byte = self.memory[(self.pc + 1) as usize];
-            let opcode: u16 = ((first_byte as u16) << 8) | (second_byte as u16);
-            // println!("{:#06x}, high: {:#04x}, low: {:#04x}", self.pc, first_byte, second_byte);
-            let curr_instruction = Instruction::parse_opcode(opcode);
-            match curr_instruction {
-                Instruction::LD_Vx_K => match keyboard.get_first_key_down() {
-                    Some(_) => (),
-                    None => return,
-                },
-                _ => (),
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // V0-VF
+    pub fn registers(&self) -> [u8; 16] {
+        self.registers
+    }
+
+    // The I register
+    pub fn address_register(&self) -> u16 {
+        self.address_register
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Change the seed and restart the random sequence from it.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Chip8Rng::seed_from_u64(seed);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // Quirks are kept when a new rom is loaded, they describe the platform rather than the program.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        // translated blocks have the old quirks built in
+        self.blocks.clear();
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    // The backend only changes how run_frame gets through a frame, both give the same results.
+    // emulate and emulate_step always interpret.
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+        self.blocks.clear();
+    }
+
+    pub fn audio_pattern(&self) -> [u8; 16] {
+        self.audio_pattern
+    }
+
+    // XO-CHIP audio pattern playback rate in Hz for the current pitch register.
+    pub fn audio_playback_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+
+    // True once a Super Chip EXIT instruction has run, no further steps are emulated
+    // until a new rom is loaded.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    // Snapshot the whole machine, including the screen and the keys held down, into a versioned
+    // binary save state. Loading it with load_state continues execution exactly where it was saved,
+    // random numbers included.
+    pub fn save_state(&self, keyboard: &Keyboard, screen: &Screen) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(&self.registers);
+        w.u16(self.pc);
+        w.u8(self.sp);
+        w.u8(self.delay_timer_register);
+        w.u8(self.sound_timer_register);
+        w.u16(self.address_register);
+        w.bytes(&self.memory);
+        for address in self.stack.iter() {
+            w.u16(*address);
+        }
+        w.bytes(&self.rpl_flags);
+        w.bytes(&self.audio_pattern);
+        w.u8(self.pitch);
+        w.u32(self.instructions_per_frame);
+        w.bool(self.quirks.shift_uses_vy);
+        w.bool(self.quirks.load_store_increments_i);
+        w.bool(self.quirks.jump_uses_vx);
+        w.bool(self.quirks.logic_resets_vf);
+        w.bool(self.quirks.clip_sprites);
+        w.bool(self.quirks.vip_hires);
+        w.bool(self.quirks.count_collided_rows);
+        w.bool(self.quirks.sys_unimplemented);
+        w.u64(self.seed);
+        w.u64(self.rng.state());
+        w.bool(self.paused);
+        w.bool(self.exited);
+        screen.write_state(&mut w);
+        keyboard.write_state(&mut w);
+        w.finish()
+    }
+
+    // Restore a snapshot made by save_state. Nothing is changed unless the whole state is valid.
+    pub fn load_state(&mut self, data: &[u8], keyboard: &mut Keyboard, screen: &mut Screen) -> Result<(), SaveStateError> {
+        let mut r = StateReader::new(data)?;
+        let mut e = Emulator::with_seed(0);
+        e.registers = r.array()?;
+        e.pc = r.u16()?;
+        e.sp = r.u8()?;
+        if e.sp as usize >= e.stack.len() {
+            return Err(SaveStateError::InvalidData("stack pointer"));
+        }
+        e.delay_timer_register = r.u8()?;
+        e.sound_timer_register = r.u8()?;
+        e.address_register = r.u16()?;
+        e.memory.copy_from_slice(r.bytes(MEMORY_SIZE)?);
+        for address in e.stack.iter_mut() {
+            *address = r.u16()?;
+        }
+        e.rpl_flags = r.array()?;
+        e.audio_pattern = r.array()?;
+        e.pitch = r.u8()?;
+        e.instructions_per_frame = r.u32()?;
+        e.quirks = Quirks {
+            shift_uses_vy: r.bool()?,
+            load_store_increments_i: r.bool()?,
+            jump_uses_vx: r.bool()?,
+            logic_resets_vf: r.bool()?,
+            clip_sprites: r.bool()?,
+            vip_hires: r.bool()?,
+            count_collided_rows: r.bool()?,
+            sys_unimplemented: r.bool()?,
+        };
+        e.seed = r.u64()?;
+        e.rng = Chip8Rng::from_state(r.u64()?);
+        e.paused = r.bool()?;
+        e.exited = r.bool()?;
+        let new_screen = screen.read_state(&mut r)?;
+        let new_keyboard = Keyboard::read_state(&mut r)?;
+        r.finish()?;
+
+        // the backend is a setting of this emulator, not part of the emulated machine
+        e.backend = self.backend;
+        *self = e;
+        *screen = new_screen;
+        *keyboard = new_keyboard;
+        Ok(())
+    }
+
+    // Run one 60Hz frame: instructions_per_frame instructions followed by exactly one tick of
+    // the delay and sound timers. Nothing here reads the wall clock, the frontend decides when
+    // a frame is due, so headless runs are deterministic.
+    // Returns an error if an instruction can not be executed, the pc is left on that instruction.
+    pub fn run_frame(&mut self, keyboard: &Keyboard, screen: &mut Screen) -> Result<(), EmulatorError> {
+        if self.paused || self.exited { return Ok(()); }
+        match self.backend {
+            Backend::Interpreter => {
+                for _ in 0..self.instructions_per_frame {
+                    if !self.emulate_step(keyboard, screen)? {
+                        break;
+                    }
+                }
+            }
+            Backend::Recompiler => self.run_blocks(self.instructions_per_frame, keyboard, screen)?,
+        }
+        self.decrement_counters();
+        Ok(())
+    }
+
+    // This function handles all "External" aspects of a single opcode, i.e. waiting for input,
+    // incrememting pc or not, etc. Timers are left to run_frame.
+    // Returns false if no instruction ran, because the emulator is paused, has exited, or is waiting for a key.
+    pub fn emulate_step(&mut self, keyboard: &Keyboard, screen: &mut Screen) -> Result<bool, EmulatorError> {
+        if self.paused || self.exited { return Ok(false); }
+        let (opcode, decoded) = self.fetch_decoded()?;
+        let curr_instruction = decoded.instruction;
+        match curr_instruction {
+            Instruction::LD_Vx_K => match keyboard.get_first_key_down() {
+                Some(_) => (),
+                None => return Ok(false),
+            },
+            _ => (),
+        }
+        self.execute(opcode, decoded, keyboard, screen)?;
+        self.advance_pc(curr_instruction);
+        Ok(true)
+    }
+
+    // Move the pc past an instruction that has just run.
+    fn advance_pc(&mut self, instruction: Instruction) {
+        // match all jump/call instructions and do not increment the pc.
+        match instruction {
+            Instruction::JP_addr => (),
+            Instruction::JP_V0 => (),
+            Instruction::CALL_addr => (),
+            Instruction::EXIT => (),
+            // the only 4 byte instruction, its address is stored in the next 2 bytes.
+            Instruction::LD_I_long => self.pc = self.pc.wrapping_add(4),
+            _ => self.pc = self.pc.wrapping_add(2),
+        }
+    }
+
+    // The Recompiler backend's version of calling emulate_step budget times: run up to budget
+    // instructions from translated blocks, stopping early when waiting for a key or on exit.
+    fn run_blocks(&mut self, budget: u32, keyboard: &Keyboard, screen: &mut Screen) -> Result<(), EmulatorError> {
+        let mut remaining = budget;
+        while remaining > 0 && !self.paused && !self.exited {
+            let block = match self.blocks.get(self.pc) {
+                Some(block) => block,
+                None => {
+                    // report running off the end of memory the same way as the interpreter
+                    self.fetch_opcode()?;
+                    self.blocks.insert(translate_block(&self.memory, self.pc, &self.quirks))
+                }
+            };
+            for &(address, op) in &block.ops {
+                if remaining == 0 {
+                    self.pc = address;
+                    return Ok(());
+                }
+                match op {
+                    MicroOp::Interpret { opcode, decoded } => {
+                        self.pc = address;
+                        if decoded.instruction == Instruction::LD_Vx_K && keyboard.get_first_key_down().is_none() {
+                            return Ok(());
+                        }
+                        self.execute(opcode, decoded, keyboard, screen)?;
+                        self.advance_pc(decoded.instruction);
+                    }
+                    _ => op.run(&mut self.registers),
+                }
+                remaining -= 1;
             }
-            self.emulate(opcode, &keyboard, screen);
-            // match all jump/call instructions and do not increment the pc.
-            match curr_instruction {
-                Instruction::JP_addr => (),
-                Instruction::JP_V0 => (),
-                Instruction::CALL_addr => (),
-                _ => self.pc += 2,
+            if !matches!(block.ops.last(), Some((_, MicroOp::Interpret { .. }))) {
+                self.pc = block.end;
             }
+        }
+        Ok(())
+    }
+
+    // Read the 2 byte opcode at the pc
+    fn fetch_opcode(&self) -> Result<u16, EmulatorError> {
+        let pc = self.pc as usize;
+        if pc + 1 >= MEMORY_SIZE {
+            return Err(EmulatorError::MemoryOutOfRange {
+                pc: self.pc,
+                opcode: (self.memory[pc] as u16) << 8,
+                address: pc + 1,
+            });
+        }
+        Ok(((self.memory[pc] as u16) << 8) | (self.memory[pc + 1] as u16))
     }
+
+    // The opcode at the pc and its decoding, from the cache if the pc has been here before.
+    fn fetch_decoded(&mut self) -> Result<(u16, DecodedInstruction), EmulatorError> {
+        if self.decode_cache.is_empty() {
+            self.decode_cache.resize(MEMORY_SIZE, None);
+        }
+        let pc = self.pc as usize;
+        if let Some(cached) = self.decode_cache[pc] {
+            return Ok(cached);
+        }
+        let opcode = self.fetch_opcode()?;
+        let entry = (opcode, decode(opcode));
+        self.decode_cache[pc] = Some(entry);
+        Ok(entry)
     }
 
-    pub fn emulate(&mut self, opcode: u16, keyboard: &Keyboard, screen: &mut Screen) {
-        let high_byte: u8 = ((opcode >> 8) & 0xFF) as u8;
-        let low_byte: u8 = (opcode & 0xFF) as u8;
+    // Forget the cached instructions and translated blocks that include any of the len bytes written
+    // at start. An opcode is 2 bytes, so the one starting at the byte before start changes too.
+    fn invalidate_decode_cache(&mut self, start: usize, len: usize) {
+        self.blocks.invalidate(start, len);
+        if self.decode_cache.is_empty() {
+            return;
+        }
+        for entry in &mut self.decode_cache[start.saturating_sub(1)..(start + len).min(MEMORY_SIZE)] {
+            *entry = None;
+        }
+    }
 
-        match Instruction::parse_opcode(opcode) {
-            Instruction::SYS_addr => self.sys_addr(),
+    // Check that the len bytes starting at I are inside memory and return I as an index.
+    fn address_register_range(&self, opcode: u16, len: usize) -> Result<usize, EmulatorError> {
+        let start = self.address_register as usize;
+        if start + len > MEMORY_SIZE {
+            return Err(EmulatorError::MemoryOutOfRange {
+                pc: self.pc,
+                opcode,
+                address: start,
+            });
+        }
+        Ok(start)
+    }
+
+    pub fn emulate(&mut self, opcode: u16, keyboard: &Keyboard, screen: &mut Screen) -> Result<(), EmulatorError> {
+        self.execute(opcode, decode(opcode), keyboard, screen)
+    }
+
+    // Run an instruction that has already been decoded from opcode.
+    fn execute(
+        &mut self,
+        opcode: u16,
+        decoded: DecodedInstruction,
+        keyboard: &Keyboard,
+        screen: &mut Screen,
+    ) -> Result<(), EmulatorError> {
+        let DecodedInstruction { instruction, x, y, n, kk, nnn } = decoded;
+
+        match instruction {
+            Instruction::SYS_addr => self.sys_addr(opcode, nnn, screen)?,
             Instruction::CLS => self.cls(screen),
-            Instruction::RET => self.ret(),
-            Instruction::JP_addr => self.jp_addr(opcode),
-            Instruction::CALL_addr => self.call_addr(opcode),
-            Instruction::SE_Vx => self.se_vx(high_byte, low_byte),
-            Instruction::SNE_Vx => self.sne_vx(high_byte, low_byte),
-            Instruction::SE_Vx_Vy => self.se_vx_vy(high_byte, low_byte),
-            Instruction::LD_Vx => self.ld_vx(high_byte, low_byte),
-            Instruction::ADD_Vx => self.add_vx(high_byte, low_byte),
-            Instruction::LD_Vx_Vy => self.ld_vx_vy(high_byte, low_byte),
-            Instruction::OR_Vx_Vy => self.or_vx_vy(high_byte, low_byte),
-            Instruction::AND_Vx_Vy => self.and_vx_vy(high_byte, low_byte),
-            Instruction::XOR_Vx_Vy => self.xor_vx_vy(high_byte, low_byte),
-            Instruction::ADD_Vx_Vy => self.add_vx_vy(high_byte, low_byte),
-            Instruction::SUB_Vx_Vy => self.sub_vx_vy(high_byte, low_byte),
-            Instruction::SHR_Vx => self.shr_vx(high_byte),
-            Instruction::SUBN_Vx_Vy => self.subn_vx_vy(high_byte, low_byte),
-            Instruction::SHL_Vx => self.shl_vx(high_byte),
-            Instruction::SNE_Vx_Vy => self.sne_vx_vy(high_byte, low_byte),
-            Instruction::LD_I => self.ld_i(opcode),
-            Instruction::JP_V0 => self.jp_v0(opcode),
-            Instruction::RND_Vx => self.rnd_vx(high_byte, low_byte),
-            Instruction::DRW_Vx_Vy => self.drw_vx_vy(high_byte, low_byte, screen),
-            Instruction::SKP_Vx => self.skp_vx(high_byte, keyboard),
-            Instruction::SKNP_Vx => self.sknp_vx(high_byte, keyboard),
-            Instruction::LD_Vx_DT => self.ld_vx_dt(high_byte),
-            Instruction::LD_Vx_K => self.ld_vx_k(high_byte, &keyboard),
-            Instruction::LD_DT_Vx => self.ld_dt_vx(high_byte),
-            Instruction::LD_ST_Vx => self.ld_st_vx(high_byte),
-            Instruction::ADD_I_Vx => self.add_i_vx(high_byte),
-            Instruction::LD_F_Vx => self.ld_f_vx(high_byte),
-            Instruction::LD_B_Vx => self.ld_b_vx(high_byte),
-            Instruction::LD_I_Vx => self.ld_i_vx(high_byte),
-            Instruction::LD_Vx_I => self.ld_vx_i(high_byte),
-            Instruction::SCD_nibble => self.missing_opcode(opcode),
-            Instruction::SCR => self.missing_opcode(opcode),
-            Instruction::SCL => self.missing_opcode(opcode),
-            Instruction::EXIT => self.missing_opcode(opcode),
-            Instruction::LOW => self.missing_opcode(opcode),
-            Instruction::HIGH => self.missing_opcode(opcode),
-            Instruction::DRW_Vx_Vy_0 => self.missing_opcode(opcode),
-            Instruction::LD_HF_Vx => self.missing_opcode(opcode),
-            Instruction::LD_R_Vx => self.missing_opcode(opcode),
-            Instruction::LD_Vx_R => self.missing_opcode(opcode),
-            Instruction::Invalid_Instruction => panic!("INVALID OP CODE"),
+            Instruction::RET => self.ret(opcode)?,
+            Instruction::JP_addr => self.jp_addr(nnn, screen),
+            Instruction::CALL_addr => self.call_addr(opcode, nnn)?,
+            Instruction::SE_Vx => self.se_vx(x, kk),
+            Instruction::SNE_Vx => self.sne_vx(x, kk),
+            Instruction::SE_Vx_Vy => self.se_vx_vy(x, y),
+            Instruction::LD_Vx => self.ld_vx(x, kk),
+            Instruction::ADD_Vx => self.add_vx(x, kk),
+            Instruction::LD_Vx_Vy => self.ld_vx_vy(x, y),
+            Instruction::OR_Vx_Vy => self.or_vx_vy(x, y),
+            Instruction::AND_Vx_Vy => self.and_vx_vy(x, y),
+            Instruction::XOR_Vx_Vy => self.xor_vx_vy(x, y),
+            Instruction::ADD_Vx_Vy => self.add_vx_vy(x, y),
+            Instruction::SUB_Vx_Vy => self.sub_vx_vy(x, y),
+            Instruction::SHR_Vx => self.shr_vx(x, y),
+            Instruction::SUBN_Vx_Vy => self.subn_vx_vy(x, y),
+            Instruction::SHL_Vx => self.shl_vx(x, y),
+            Instruction::SNE_Vx_Vy => self.sne_vx_vy(x, y),
+            Instruction::LD_I => self.ld_i(nnn),
+            Instruction::JP_V0 => self.jp_v0(x, nnn),
+            Instruction::RND_Vx => self.rnd_vx(x, kk),
+            Instruction::DRW_Vx_Vy => self.drw_vx_vy(opcode, x, y, n, screen)?,
+            Instruction::SKP_Vx => self.skp_vx(x, keyboard),
+            Instruction::SKNP_Vx => self.sknp_vx(x, keyboard),
+            Instruction::LD_Vx_DT => self.ld_vx_dt(x),
+            Instruction::LD_Vx_K => self.ld_vx_k(x, keyboard),
+            Instruction::LD_DT_Vx => self.ld_dt_vx(x),
+            Instruction::LD_ST_Vx => self.ld_st_vx(x),
+            Instruction::ADD_I_Vx => self.add_i_vx(x),
+            Instruction::LD_F_Vx => self.ld_f_vx(x),
+            Instruction::LD_B_Vx => self.ld_b_vx(opcode, x)?,
+            Instruction::LD_I_Vx => self.ld_i_vx(opcode, x)?,
+            Instruction::LD_Vx_I => self.ld_vx_i(opcode, x)?,
+            Instruction::SCD_nibble => self.scd_nibble(n, screen),
+            Instruction::SCR => self.scr(screen),
+            Instruction::SCL => self.scl(screen),
+            Instruction::EXIT => self.exit(),
+            Instruction::LOW => self.low(screen),
+            Instruction::HIGH => self.high(screen),
+            Instruction::DRW_Vx_Vy_0 => self.drw_vx_vy_0(opcode, x, y, screen)?,
+            Instruction::LD_HF_Vx => self.ld_hf_vx(x),
+            Instruction::LD_R_Vx => self.ld_r_vx(x),
+            Instruction::LD_Vx_R => self.ld_vx_r(x),
+            Instruction::LD_I_long => self.ld_i_long(),
+            // the plane mask is stored where x normally is
+            Instruction::PLANE_nibble => self.plane_nibble(x.0, screen),
+            Instruction::AUDIO => self.audio(opcode)?,
+            Instruction::PITCH_Vx => self.pitch_vx(x),
+            Instruction::SAVE_Vx_Vy => self.save_vx_vy(opcode, x, y)?,
+            Instruction::LOAD_Vx_Vy => self.load_vx_vy(opcode, x, y)?,
+            Instruction::Invalid_Instruction => {
+                return Err(EmulatorError::InvalidOpcode { pc: self.pc, opcode })
+            }
         }
+        Ok(())
     }
 
-    fn sys_addr(&mut self) {
-        // This instruction is ignored in modern emulators
-        return;
+    // Skip the instruction after the current one. XO-CHIP's F000 NNNN is 4 bytes long,
+    // so skipping over it has to move the pc twice as far.
+    fn skip_next_instruction(&mut self) {
+        let next = self.pc as usize + 2;
+        if self.memory[next % MEMORY_SIZE] == 0xF0 && self.memory[(next + 1) % MEMORY_SIZE] == 0x00 {
+            self.pc = self.pc.wrapping_add(4);
+        } else {
+            self.pc = self.pc.wrapping_add(2);
+        }
+    }
+
+    fn sys_addr(&mut self, opcode: u16, nnn: Address, screen: &mut Screen) -> Result<(), EmulatorError> {
+        // This instruction is ignored in modern emulators, except for the VIP hi-res
+        // interpreter's 0230 which clears the 64x64 screen.
+        if self.quirks.vip_hires && nnn.0 == 0x230 {
+            self.cls(screen);
+        } else if self.quirks.sys_unimplemented {
+            return Err(EmulatorError::UnimplementedInstruction { pc: self.pc, opcode });
+        }
+        Ok(())
     }
 
     fn cls(&self, screen: &mut Screen) {
+        // clears the selected XO-CHIP planes, the first plane unless the rom selects others
         screen.clear_screen();
     }
 
-    fn ret(&mut self) {
+    fn ret(&mut self, opcode: u16) -> Result<(), EmulatorError> {
         // return from a subroutine
+        if self.sp == 0 {
+            return Err(EmulatorError::StackUnderflow { pc: self.pc, opcode });
+        }
         self.pc = self.stack[self.sp as usize];
         self.sp -= 1;
+        Ok(())
     }
 
-    fn jp_addr(&mut self, opcode: u16) {
+    fn jp_addr(&mut self, nnn: Address, screen: &mut Screen) {
         // jumps to address nnn
-        self.pc = opcode & 0x0FFF;
+        // VIP hi-res roms start with JP 0x260, where the hi-res interpreter was loaded. It
+        // switches to the 64x64 screen and runs the rom from 0x2C0.
+        if self.quirks.vip_hires && self.pc == 0x200 && nnn.0 == 0x260 {
+            screen.set_resolution(Resolution::VipHires);
+            self.pc = 0x2C0;
+        } else {
+            self.pc = nnn.0;
+        }
     }
 
-    fn call_addr(&mut self, opcode: u16) {
+    fn call_addr(&mut self, opcode: u16, nnn: Address) -> Result<(), EmulatorError> {
         // call subroutine
+        // stack[0] is never used, so 15 calls can be nested
+        if self.sp as usize + 1 >= self.stack.len() {
+            return Err(EmulatorError::StackOverflow { pc: self.pc, opcode });
+        }
         self.sp += 1;
         self.stack[self.sp as usize] = self.pc;
-        self.pc = opcode & 0x0FFF;
+        self.pc = nnn.0;
+        Ok(())
     }
 
-    fn se_vx(&mut self, high_byte: u8, low_byte: u8) {
+    fn se_vx(&mut self, x: Register, kk: Byte) {
         // compare Vx to kk, skip next instruction if equal
-        let second_nibble = high_byte & 0x0F;
-        if self.registers[second_nibble as usize] == low_byte {
-            self.pc += 2;
+        if self.registers[x.index()] == kk.0 {
+            self.skip_next_instruction();
         }
     }
 
-    fn sne_vx(&mut self, high_byte: u8, low_byte: u8) {
+    fn sne_vx(&mut self, x: Register, kk: Byte) {
         // Compare Vx to kk, skip if not equal
-        let second_nibble = high_byte & 0x0F;
-        if self.registers[second_nibble as usize] != low_byte {
-            self.pc += 2;
+        if self.registers[x.index()] != kk.0 {
+            self.skip_next_instruction();
         }
     }
 
-    fn se_vx_vy(&mut self, high_byte: u8, low_byte: u8) {
+    fn se_vx_vy(&mut self, x: Register, y: Register) {
         // compare Vx to Vy, skip if equal
-        let second_nibble = high_byte & 0x0F;
-        let third_nibble = (low_byte & 0xF0) >> 4;
-        if self.registers[second_nibble as usize] == self.registers[third_nibble as usize] {
-            self.pc += 2;
+        if self.registers[x.index()] == self.registers[y.index()] {
+            self.skip_next_instruction();
         }
     }
 
-    fn ld_vx(&mut self, high_byte: u8, low_byte: u8) {
+    fn ld_vx(&mut self, x: Register, kk: Byte) {
         // load kk into Vx
-        let second_nibble = high_byte & 0x0F;
-        self.registers[second_nibble as usize] = low_byte;
+        self.registers[x.index()] = kk.0;
     }
 
-    fn add_vx(&mut self, high_byte: u8, low_byte: u8) {
+    fn add_vx(&mut self, x: Register, kk: Byte) {
         // Add kk to Vx and store in Vx
-        let second_nibble = high_byte & 0x0F;
-        self.registers[second_nibble as usize] =
-            self.registers[second_nibble as usize].wrapping_add(low_byte);
+        self.registers[x.index()] =
+            self.registers[x.index()].wrapping_add(kk.0);
     }
 
-    fn ld_vx_vy(&mut self, high_byte: u8, low_byte: u8) {
+    fn ld_vx_vy(&mut self, x: Register, y: Register) {
         // Set Vx = Vy
-        let second_nibble = high_byte & 0x0F;
-        let third_nibble = (low_byte & 0xF0) >> 4;
-        self.registers[second_nibble as usize] = self.registers[third_nibble as usize];
+        self.registers[x.index()] = self.registers[y.index()];
     }
 
-    fn or_vx_vy(&mut self, high_byte: u8, low_byte: u8) {
+    fn or_vx_vy(&mut self, x: Register, y: Register) {
         // set Vx to Vx OR Vy
-        let second_nibble = high_byte & 0x0F;
-        let third_nibble = (low_byte & 0xF0) >> 4;
-        self.registers[second_nibble as usize] =
-            self.registers[second_nibble as usize] | self.registers[third_nibble as usize];
+        self.registers[x.index()] =
+            self.registers[x.index()] | self.registers[y.index()];
+        if self.quirks.logic_resets_vf {
+            self.registers[self.flag_register_index] = 0;
+        }
     }
 
-    fn and_vx_vy(&mut self, high_byte: u8, low_byte: u8) {
+    fn and_vx_vy(&mut self, x: Register, y: Register) {
         // set Vx to Vx AND Vy
-        let second_nibble = high_byte & 0x0F;
-        let third_nibble = (low_byte & 0xF0) >> 4;
-        self.registers[second_nibble as usize] =
-            self.registers[second_nibble as usize] & self.registers[third_nibble as usize];
+        self.registers[x.index()] =
+            self.registers[x.index()] & self.registers[y.index()];
+        if self.quirks.logic_resets_vf {
+            self.registers[self.flag_register_index] = 0;
+        }
     }
 
-    fn xor_vx_vy(&mut self, high_byte: u8, low_byte: u8) {
+    fn xor_vx_vy(&mut self, x: Register, y: Register) {
         // set Vx to Vx XOR Vy
-        let second_nibble = high_byte & 0x0F;
-        let third_nibble = (low_byte & 0xF0) >> 4;
-        self.registers[second_nibble as usize] =
-            self.registers[second_nibble as usize] ^ self.registers[third_nibble as usize];
+        self.registers[x.index()] =
+            self.registers[x.index()] ^ self.registers[y.index()];
+        if self.quirks.logic_resets_vf {
+            self.registers[self.flag_register_index] = 0;
+        }
     }
 
-    fn add_vx_vy(&mut self, high_byte: u8, low_byte: u8) {
+    fn add_vx_vy(&mut self, x: Register, y: Register) {
         // set Vx to Vx + Vy. Only keep lowest 8 bits, set VF = 1 if overflow
         // I can't find what "lowest 8 bits kept" means, so I am assuming its a normal overflow
-        let second_nibble = high_byte & 0x0F;
-        let third_nibble = (low_byte & 0xF0) >> 4;
-        let result: u16 = (self.registers[second_nibble as usize] as u16)
-            + (self.registers[third_nibble as usize] as u16);
+        let result: u16 = (self.registers[x.index()] as u16)
+            + (self.registers[y.index()] as u16);
         // set carry flag
         if result > 255 {
             self.registers[self.flag_register_index as usize] = 1;
         } else {
             self.registers[self.flag_register_index as usize] = 0;
         }
-        self.registers[second_nibble as usize] = self.registers[second_nibble as usize]
-            .wrapping_add(self.registers[third_nibble as usize]);
+        self.registers[x.index()] = self.registers[x.index()]
+            .wrapping_add(self.registers[y.index()]);
     }
 
-    fn sub_vx_vy(&mut self, high_byte: u8, low_byte: u8) {
+    fn sub_vx_vy(&mut self, x: Register, y: Register) {
         // set Vx = Vx - Vy. If underflow (Vy > Vx), set Vf to 0 else 1.
-        let second_nibble = high_byte & 0x0F;
-        let third_nibble = (low_byte & 0xF0) >> 4;
         // No underflow
-        if self.registers[second_nibble as usize] > self.registers[third_nibble as usize] {
+        if self.registers[x.index()] > self.registers[y.index()] {
             self.registers[self.flag_register_index as usize] = 1;
         } else {
             self.registers[self.flag_register_index as usize] = 0;
         }
-        self.registers[second_nibble as usize] = self.registers[second_nibble as usize]
-            .wrapping_sub(self.registers[third_nibble as usize]);
+        self.registers[x.index()] = self.registers[x.index()]
+            .wrapping_sub(self.registers[y.index()]);
     }
 
-    fn shr_vx(&mut self, high_byte: u8) {
+    fn shr_vx(&mut self, x: Register, y: Register) {
         // store least significant bit of Vx into Vf, then Vx >>= 1
-        let second_nibble = high_byte & 0x0F;
-        self.registers[self.flag_register_index] = self.registers[second_nibble as usize] & 0x01;
-        self.registers[second_nibble as usize] = self.registers[second_nibble as usize] >> 1;
+        // With the shift_uses_vy quirk Vy is shifted instead and the result stored in Vx.
+        let source = if self.quirks.shift_uses_vy {
+            self.registers[y.index()]
+        } else {
+            self.registers[x.index()]
+        };
+        self.registers[x.index()] = source >> 1;
+        self.registers[self.flag_register_index] = source & 0x01;
     }
 
-    fn subn_vx_vy(&mut self, high_byte: u8, low_byte: u8) {
+    fn subn_vx_vy(&mut self, x: Register, y: Register) {
         // set Vx = Vy - Vx, set Vf = 1 if no underflow
-        let second_nibble = high_byte & 0x0F;
-        let third_nibble = (low_byte & 0xF0) >> 4;
         // No underflow, set to 1
-        if self.registers[third_nibble as usize] > self.registers[second_nibble as usize] {
+        if self.registers[y.index()] > self.registers[x.index()] {
             self.registers[self.flag_register_index] = 1;
         } else {
             self.registers[self.flag_register_index] = 0;
         }
-        self.registers[second_nibble as usize] = self.registers[third_nibble as usize]
-            .wrapping_sub(self.registers[second_nibble as usize]);
+        self.registers[x.index()] = self.registers[y.index()]
+            .wrapping_sub(self.registers[x.index()]);
     }
 
-    fn shl_vx(&mut self, high_byte: u8) {
+    fn shl_vx(&mut self, x: Register, y: Register) {
         // set Vf to most significant bit of Vx, then shift Vx left 1
-        let second_nibble = high_byte & 0x0F;
-        self.registers[self.flag_register_index] =
-            (self.registers[second_nibble as usize] & 0x80) >> 7;
-        self.registers[second_nibble as usize] = self.registers[second_nibble as usize] << 1;
+        // With the shift_uses_vy quirk Vy is shifted instead and the result stored in Vx.
+        let source = if self.quirks.shift_uses_vy {
+            self.registers[y.index()]
+        } else {
+            self.registers[x.index()]
+        };
+        self.registers[x.index()] = source << 1;
+        self.registers[self.flag_register_index] = (source & 0x80) >> 7;
     }
 
-    fn sne_vx_vy(&mut self, high_byte: u8, low_byte: u8) {
+    fn sne_vx_vy(&mut self, x: Register, y: Register) {
         // skip instruction if Vx != Vy
-        let second_nibble = high_byte & 0x0F;
-        let third_nibble = (low_byte & 0xF0) >> 4;
-        if self.registers[second_nibble as usize] != self.registers[third_nibble as usize] {
-            self.pc += 2;
+        if self.registers[x.index()] != self.registers[y.index()] {
+            self.skip_next_instruction();
         }
     }
 
-    fn ld_i(&mut self, opcode: u16) {
+    fn ld_i(&mut self, nnn: Address) {
         // set I to nnn
-        let address = opcode & 0x0FFF;
-        self.address_register = address;
+        self.address_register = nnn.0;
     }
 
-    fn jp_v0(&mut self, opcode: u16) {
+    fn jp_v0(&mut self, x: Register, nnn: Address) {
         // set pc to nnn + V0
-        let address = opcode & 0x0FFF;
-        self.pc = self.registers[0x0] as u16 + address;
+        // With the jump_uses_vx quirk this is Bxnn, and pc is set to xnn + Vx
+        let register = if self.quirks.jump_uses_vx { x.index() } else { 0x0 };
+        self.pc = self.registers[register] as u16 + nnn.0;
     }
 
-    fn rnd_vx(&mut self, high_byte: u8, low_byte: u8) {
-        let second_nibble = high_byte & 0x0F;
-        let rn: u8 = rand::thread_rng().gen_range(0..=255);
-        self.registers[second_nibble as usize] = rn & low_byte;
+    fn rnd_vx(&mut self, x: Register, kk: Byte) {
+        let rn: u8 = self.rng.gen_range(0..=255);
+        self.registers[x.index()] = rn & kk.0;
     }
 
-    fn drw_vx_vy(&mut self, high_byte: u8, low_byte: u8, screen: &mut Screen) {
+    fn drw_vx_vy(&mut self, opcode: u16, x: Register, y: Register, n: Nibble, screen: &mut Screen) -> Result<(), EmulatorError> {
         // draw a sprite that is n bytes, from memory address I, starting at coordinates (Vx,Vy).
         // We XOR the sprite with the screen, so if an on pixel is already set at any point in the sprite, it is set to off and VF is set.
         // From my understanding the only way a pixel is set to off is by this collision.
         // If a sprite goes off the screen, it will wrap around to the other side. I found conflicting information on the specifics of this,
-        // but my in implementation it will wrap part of a sprite.
-        let second_nibble = high_byte & 0x0F;
-        let third_nibble = (low_byte & 0xF0) >> 4;
-        let last_nibble = low_byte & 0x0F;
-        let mut flag_set: u8 = 0;
-        let x = self.registers[second_nibble as usize];
-        let y = self.registers[third_nibble as usize];
-        for (i, sprite_byte) in self.memory[(self.address_register as usize)
-            ..(self.address_register + (last_nibble as u16)) as usize]
-            .iter()
-            .enumerate()
-        {
-            if screen.set_byte_pixels(*sprite_byte, x as u32, (y as u32) + (i as u32)) {
-                flag_set = 1;
-            }
+        // but my in implementation it will wrap part of a sprite. The clip_sprites quirk puts the screen in Clip mode,
+        // wrapping only the starting coordinate and clipping the rest of the sprite.
+        // XO-CHIP gives each selected plane its own copy of the sprite data one after another,
+        // the first selected plane's data comes first. A collision on any plane sets VF.
+        let rows = n.0 as usize;
+        let planes: Vec<usize> = screen.selected_planes().collect();
+        let start = self.address_register_range(opcode, rows * planes.len().max(1))?;
+        let mut report = SpriteReport::default();
+        screen.set_sprite_mode(self.quirks.sprite_mode());
+        let vx = self.registers[x.index()] as u32;
+        let vy = self.registers[y.index()] as u32;
+        for (k, plane) in planes.into_iter().enumerate() {
+            let data = start + k * rows;
+            report = report.merge(screen.draw_sprite(plane, &self.memory[data..data + rows], vx, vy));
+        }
+        self.registers[self.flag_register_index] = self.sprite_flag(report, screen);
+        Ok(())
+    }
+
+    // VF after drawing a sprite: 1 if it collided, or with the count_collided_rows quirk
+    // in hi-res mode the number of rows that collided or were clipped.
+    fn sprite_flag(&self, report: SpriteReport, screen: &Screen) -> u8 {
+        if self.quirks.count_collided_rows && screen.resolution() == Some(Resolution::High) {
+            report.row_count()
+        } else {
+            report.collided() as u8
         }
-        self.registers[self.flag_register_index] = flag_set;
     }
 
-    fn skp_vx(&mut self, high_byte: u8, keyboard: &Keyboard) {
-        // if key with value Vx is down, skip instruction
-        let second_nibble = high_byte & 0x0F;
-        if keyboard.is_key_down(self.registers[second_nibble as usize]) {
-            self.pc += 2;
+    fn skp_vx(&mut self, x: Register, keyboard: &Keyboard) {
+        // if key with value Vx is down, skip instruction. Only the low nibble of Vx selects a key.
+        if keyboard.is_key_down(self.registers[x.index()] & 0xF) {
+            self.skip_next_instruction();
         }
     }
 
-    fn sknp_vx(&mut self, high_byte: u8, keyboard: &Keyboard) {
+    fn sknp_vx(&mut self, x: Register, keyboard: &Keyboard) {
         // skip instruction if key is up
-        let second_nibble = high_byte & 0x0F;
-        if !keyboard.is_key_down(self.registers[second_nibble as usize]) {
-            self.pc += 2;
+        if !keyboard.is_key_down(self.registers[x.index()] & 0xF) {
+            self.skip_next_instruction();
         }
     }
 
-    fn ld_vx_dt(&mut self, high_byte: u8) {
-        let sn = high_byte & 0x0F;
-        self.registers[sn as usize] = self.delay_timer_register;
+    fn ld_vx_dt(&mut self, x: Register) {
+        self.registers[x.index()] = self.delay_timer_register;
     }
 
-    fn ld_vx_k(&mut self, high_byte: u8, keyboard: &Keyboard) {
-        // This function will halt execution until a key is pressed. Then
-        // store that key in Vx. Halting will occur outside of this function,
-        // and it will assume that a key has already been pressed.
-        let sn = high_byte & 0x0F;
-        self.registers[sn as usize] = match keyboard.get_first_key_down() {
-            Some(i) => i,
-            None => panic!(
-                "Keyboard had no keys down, execution should have paused prior to function call."
-            ),
+    fn ld_vx_k(&mut self, x: Register, keyboard: &Keyboard) {
+        // Wait for a key press, then store that key in Vx. With no key down nothing changes,
+        // emulate_step then leaves the pc on this instruction so it runs again next time.
+        if let Some(i) = keyboard.get_first_key_down() {
+            self.registers[x.index()] = i;
         }
     }
 
-    fn ld_dt_vx(&mut self, high_byte: u8) {
+    fn ld_dt_vx(&mut self, x: Register) {
         // set DT = Vx
-        let sn = high_byte & 0x0F;
-        self.delay_timer_register = self.registers[sn as usize];
+        self.delay_timer_register = self.registers[x.index()];
     }
 
-    fn ld_st_vx(&mut self, high_byte: u8) {
+    fn ld_st_vx(&mut self, x: Register) {
         // set ST = Vx
-        let sn = high_byte & 0x0F;
-        self.sound_timer_register = self.registers[sn as usize];
+        self.sound_timer_register = self.registers[x.index()];
     }
 
-    fn add_i_vx(&mut self, high_byte: u8) {
+    fn add_i_vx(&mut self, x: Register) {
         // I = I + Vx
-        let sn = high_byte & 0x0F;
         self.address_register = self
             .address_register
-            .wrapping_add(self.registers[sn as usize] as u16);
+            .wrapping_add(self.registers[x.index()] as u16);
     }
 
-    fn ld_f_vx(&mut self, high_byte: u8) {
+    fn ld_f_vx(&mut self, x: Register) {
         // This gets the hexidecimal digits that are stored in the
         // begginning section of memory (first 512 bytes).
         // I think these can be stored in an arbitary location, but I'm not sure.
         // I = sprite_mem_index
-        let sn = high_byte & 0x0F;
         self.address_register =
-            self.sprite_memory_index as u16 + (5 * self.registers[sn as usize] as u16);
+            self.sprite_memory_index as u16 + (5 * self.registers[x.index()] as u16);
     }
 
-    fn ld_b_vx(&mut self, high_byte: u8) {
+    fn ld_b_vx(&mut self, opcode: u16, x: Register) -> Result<(), EmulatorError> {
         // take the decimal value of Vx, place the hundres digit in memory[I], tens in memory[I + 1], and ones in memory[I+2]
-        let sn = high_byte & 0x0F;
-        let value = self.registers[sn as usize];
-        self.memory[self.address_register as usize] = value / 100;
-        self.memory[self.address_register as usize + 1] = (value % 100) / 10;
-        self.memory[self.address_register as usize + 2] = value % 10;
+        let start = self.address_register_range(opcode, 3)?;
+        let value = self.registers[x.index()];
+        self.memory[start] = value / 100;
+        self.memory[start + 1] = (value % 100) / 10;
+        self.memory[start + 2] = value % 10;
+        self.invalidate_decode_cache(start, 3);
+        Ok(())
     }
 
-    fn ld_i_vx(&mut self, high_byte: u8) {
+    fn ld_i_vx(&mut self, opcode: u16, x: Register) -> Result<(), EmulatorError> {
         // store registers V0..Vx in memory starting at memory[I]
-        let sn = high_byte & 0x0F;
-        for i in 0..=sn as usize {
-            self.memory[self.address_register as usize + i] = self.registers[i];
+        let start = self.address_register_range(opcode, x.index() + 1)?;
+        for i in 0..=x.index() {
+            self.memory[start + i] = self.registers[i];
+        }
+        self.invalidate_decode_cache(start, x.index() + 1);
+        if self.quirks.load_store_increments_i {
+            self.address_register = self.address_register.wrapping_add(x.0 as u16 + 1);
         }
+        Ok(())
     }
 
-    fn ld_vx_i(&mut self, high_byte: u8) {
+    fn ld_vx_i(&mut self, opcode: u16, x: Register) -> Result<(), EmulatorError> {
         // read values from memory[I..I+x] into register V0..Vx
-        let sn = high_byte & 0x0F;
-        for i in 0..=sn as usize {
-            self.registers[i] = self.memory[self.address_register as usize + i];
+        let start = self.address_register_range(opcode, x.index() + 1)?;
+        for i in 0..=x.index() {
+            self.registers[i] = self.memory[start + i];
+        }
+        if self.quirks.load_store_increments_i {
+            self.address_register = self.address_register.wrapping_add(x.0 as u16 + 1);
         }
+        Ok(())
+    }
+
+    fn scd_nibble(&mut self, n: Nibble, screen: &mut Screen) {
+        // Super Chip: scroll the display down n pixels
+        screen.scroll_down(n.0 as u32);
+    }
+
+    fn scr(&mut self, screen: &mut Screen) {
+        // Super Chip: scroll the display right 4 pixels
+        screen.scroll_right();
+    }
+
+    fn scl(&mut self, screen: &mut Screen) {
+        // Super Chip: scroll the display left 4 pixels
+        screen.scroll_left();
     }
 
-    fn missing_opcode(&self, opcode: u16) {
-        println!("Missing opcode! {:#06x} \n{:?}", opcode, *self);
-        panic!();
+    fn exit(&mut self) {
+        // Super Chip: exit the interpreter. The pc is left on this instruction and
+        // emulate_step and run_frame will not run anything else until a new rom is loaded.
+        self.exited = true;
     }
+
+    fn low(&mut self, screen: &mut Screen) {
+        // Super Chip: switch to the 64x32 low resolution mode
+        screen.set_resolution(Resolution::Low);
+    }
+
+    fn high(&mut self, screen: &mut Screen) {
+        // Super Chip: switch to the 128x64 high resolution mode
+        screen.set_resolution(Resolution::High);
+    }
+
+    fn drw_vx_vy_0(&mut self, opcode: u16, x: Register, y: Register, screen: &mut Screen) -> Result<(), EmulatorError> {
+        // Super Chip: draw a 16x16 sprite from memory address I at (Vx, Vy).
+        // Each row of the sprite is two bytes, so 32 bytes are read in total.
+        // Collisions and XO-CHIP planes are handled the same way as DRW_Vx_Vy.
+        let planes: Vec<usize> = screen.selected_planes().collect();
+        let start = self.address_register_range(opcode, 32 * planes.len().max(1))?;
+        let mut report = SpriteReport::default();
+        screen.set_sprite_mode(self.quirks.sprite_mode());
+        let vx = self.registers[x.index()] as u32;
+        let vy = self.registers[y.index()] as u32;
+        for (k, plane) in planes.into_iter().enumerate() {
+            let data = start + k * 32;
+            report = report.merge(screen.draw_large_sprite(plane, &self.memory[data..data + 32], vx, vy));
+        }
+        self.registers[self.flag_register_index] = self.sprite_flag(report, screen);
+        Ok(())
+    }
+
+    fn ld_hf_vx(&mut self, x: Register) {
+        // Super Chip: set I to the large 10 byte sprite for the digit in Vx
+        self.address_register =
+            self.large_sprite_memory_index as u16 + (10 * self.registers[x.index()] as u16);
+    }
+
+    fn ld_r_vx(&mut self, x: Register) {
+        // Super Chip: store registers V0..Vx in the RPL user flags
+        self.rpl_flags[..=x.index()].copy_from_slice(&self.registers[..=x.index()]);
+    }
+
+    fn ld_vx_r(&mut self, x: Register) {
+        // Super Chip: read registers V0..Vx from the RPL user flags
+        self.registers[..=x.index()].copy_from_slice(&self.rpl_flags[..=x.index()]);
+    }
+
+    fn ld_i_long(&mut self) {
+        // XO-CHIP: set I to the 16 bit address stored in the 2 bytes after this instruction
+        let first_byte = self.memory[(self.pc as usize + 2) % MEMORY_SIZE];
+        let second_byte = self.memory[(self.pc as usize + 3) % MEMORY_SIZE];
+        self.address_register = ((first_byte as u16) << 8) | (second_byte as u16);
+    }
+
+    fn plane_nibble(&mut self, mask: u8, screen: &mut Screen) {
+        // XO-CHIP: select the drawing planes used by CLS, DRW and the scroll instructions
+        screen.set_plane_mask(mask);
+    }
+
+    fn audio(&mut self, opcode: u16) -> Result<(), EmulatorError> {
+        // XO-CHIP: load the 16 byte audio pattern buffer from memory[I..I+16]
+        let start = self.address_register_range(opcode, 16)?;
+        self.audio_pattern.copy_from_slice(&self.memory[start..start + 16]);
+        Ok(())
+    }
+
+    fn pitch_vx(&mut self, x: Register) {
+        // XO-CHIP: set the audio pattern playback pitch to Vx
+        self.pitch = self.registers[x.index()];
+    }
+
+    fn save_vx_vy(&mut self, opcode: u16, x: Register, y: Register) -> Result<(), EmulatorError> {
+        // XO-CHIP: store registers Vx..Vy in memory starting at memory[I], I is not changed.
+        // If x > y the registers are stored in reverse order.
+        let count = x.index().abs_diff(y.index());
+        let start = self.address_register_range(opcode, count + 1)?;
+        for i in 0..=count {
+            let register = if x.index() <= y.index() {
+                x.index() + i
+            } else {
+                x.index() - i
+            };
+            self.memory[start + i] = self.registers[register];
+        }
+        self.invalidate_decode_cache(start, count + 1);
+        Ok(())
+    }
+
+    fn load_vx_vy(&mut self, opcode: u16, x: Register, y: Register) -> Result<(), EmulatorError> {
+        // XO-CHIP: read registers Vx..Vy from memory starting at memory[I], I is not changed.
+        // If x > y the registers are read in reverse order.
+        let count = x.index().abs_diff(y.index());
+        let start = self.address_register_range(opcode, count + 1)?;
+        for i in 0..=count {
+            let register = if x.index() <= y.index() {
+                x.index() + i
+            } else {
+                x.index() - i
+            };
+            self.registers[register] = self.memory[start + i];
+        }
+        Ok(())
+    }
+
 }
 impl fmt::Debug for Emulator {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -656,7 +1170,32 @@ mod tests {
     // This function is just so I dont have to rewrite all my tests. Yes I know its bad
     fn no_screen_test(opcode: u16, e: &mut Emulator, k: &Keyboard) {
         let mut s = Screen::new(1, 1);
-        e.emulate(opcode, k, &mut s)
+        emulate_both(e, opcode, k, &mut s).unwrap()
+    }
+
+    // Run opcode through emulate, and through the Recompiler backend's translation of it on a copy
+    // of e, and check both backends leave the same state behind.
+    fn emulate_both(e: &mut Emulator, opcode: u16, k: &Keyboard, s: &mut Screen) -> Result<(), EmulatorError> {
+        let mut compiled = Emulator::with_seed(0);
+        let mut compiled_screen = Screen::new(1, 1);
+        compiled.load_state(&e.save_state(k, s), &mut Keyboard::new(), &mut compiled_screen).unwrap();
+        let compiled_result = match MicroOp::translate(opcode, &compiled.quirks) {
+            MicroOp::Interpret { opcode, decoded } => compiled.execute(opcode, decoded, k, &mut compiled_screen),
+            op => {
+                op.run(&mut compiled.registers);
+                Ok(())
+            }
+        };
+        let result = e.emulate(opcode, k, s);
+        assert_eq!(result, compiled_result);
+        assert!(e.save_state(k, s) == compiled.save_state(k, &compiled_screen));
+        result
+    }
+
+    fn run_steps(e: &mut Emulator, k: &Keyboard, s: &mut Screen, steps: usize) {
+        for _ in 0..steps {
+            e.emulate_step(k, s).unwrap();
+        }
     }
 
     #[test]
@@ -679,7 +1218,7 @@ mod tests {
         let z = [0, 0, 0, 0];
         let comparison = vec![z, z, c, c];
         assert_eq!(output, comparison);
-        e.emulate(opcode, &k, &mut s);
+        emulate_both(&mut e, opcode, &k, &mut s).unwrap();
 
         let output = s.screen_to_render();
         let comparison: Vec<[u8; 4]> = vec![z, z, z, z];
@@ -907,16 +1446,47 @@ mod tests {
         assert_eq!(e.pc, 0x0172);
     }
 
-    /* Currently not testing rng, have manually verified function.
-        May add unit tests in the future
     #[test]
     fn rnd_vx() {
         let opcode: u16 = 0xC1FF;
-        let (mut e, k) = set_up(opcode, Instruction::RND_Vx);
-        no_screen_test(opcode, &mut e, &k);
-        assert_eq!(1,0);
+        let (_, k) = set_up(opcode, Instruction::RND_Vx);
+        let mut a = Emulator::with_seed(42);
+        let mut b = Emulator::with_seed(42);
+        let mut a_values = Vec::new();
+        let mut b_values = Vec::new();
+        for _ in 0..16 {
+            no_screen_test(opcode, &mut a, &k);
+            no_screen_test(opcode, &mut b, &k);
+            a_values.push(a.registers[1]);
+            b_values.push(b.registers[1]);
+        }
+        assert_eq!(a_values, b_values);
+        assert!(a_values.iter().any(|value| *value != a_values[0]));
+
+        // the random value is masked with kk
+        no_screen_test(0xC10F, &mut a, &k);
+        assert_eq!(a.registers[1] & 0xF0, 0);
+    }
+
+    #[test]
+    fn seed_is_replayed_on_load() {
+        let rom = vec![0xC1, 0xFF, 0xC2, 0xFF, 0xC3, 0xFF];
+        let k = Keyboard::new();
+        let mut s = Screen::new(1, 1);
+        let mut e = Emulator::with_seed(7);
+        assert_eq!(e.seed(), 7);
+        e.load_rom(rom.clone()).unwrap();
+        run_steps(&mut e, &k, &mut s, 3);
+        let first_run = e.registers;
+        e.load_rom(rom.clone()).unwrap();
+        run_steps(&mut e, &k, &mut s, 3);
+        assert_eq!(e.registers, first_run);
+
+        e.set_seed(8);
+        e.load_rom(rom).unwrap();
+        run_steps(&mut e, &k, &mut s, 3);
+        assert_ne!(e.registers, first_run);
     }
-    */
 
     #[test]
     fn drw_vx_vy() {
@@ -932,7 +1502,7 @@ mod tests {
 
         e.registers[1] = 0;
         e.registers[2] = 3;
-        e.emulate(opcode, &k, &mut s);
+        emulate_both(&mut e, opcode, &k, &mut s).unwrap();
 
         let output = s.screen_to_render();
         let c: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
@@ -1003,6 +1573,16 @@ mod tests {
         k.set_key(10, Key::Down);
         no_screen_test(opcode, &mut e, &k);
         assert_eq!(e.registers[1], 0x0A);
+
+        // no key down: Vx is left alone and the pc stays on the instruction
+        k.set_key(10, Key::Up);
+        let pc = e.pc;
+        no_screen_test(opcode, &mut e, &k);
+        assert_eq!((e.registers[1], e.pc), (0x0A, pc));
+        let mut s = Screen::new(1, 1);
+        e.memory[pc as usize..pc as usize + 2].copy_from_slice(&opcode.to_be_bytes());
+        assert_eq!(e.emulate_step(&k, &mut s), Ok(false));
+        assert_eq!((e.registers[1], e.pc), (0x0A, pc));
     }
 
     #[test]
@@ -1152,4 +1732,690 @@ mod tests {
         assert_eq!(e.registers[13], 0x35);
         assert_eq!(e.registers[14], 0x34);
     }
+
+    #[test]
+    fn scd_nibble() {
+        let opcode: u16 = 0x00C2;
+        let (mut e, k) = set_up(opcode, Instruction::SCD_nibble);
+        let mut s = Screen::new(2, 4);
+        s.set_pixel(0, 0);
+        s.set_pixel(1, 1);
+        emulate_both(&mut e, opcode, &k, &mut s).unwrap();
+        let c: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+        let z: [u8; 4] = [0, 0, 0, 0];
+        let comparison: Vec<[u8; 4]> = vec![z, z, z, z, c, z, z, c];
+        assert_eq!(s.screen_to_render(), comparison);
+    }
+
+    #[test]
+    fn scr() {
+        let opcode: u16 = 0x00FB;
+        let (mut e, k) = set_up(opcode, Instruction::SCR);
+        let mut s = Screen::new(8, 1);
+        s.set_byte_pixels(0xC1, 0, 0);
+        emulate_both(&mut e, opcode, &k, &mut s).unwrap();
+        let c: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+        let z: [u8; 4] = [0, 0, 0, 0];
+        let comparison: Vec<[u8; 4]> = vec![z, z, z, z, c, c, z, z];
+        assert_eq!(s.screen_to_render(), comparison);
+    }
+
+    #[test]
+    fn scl() {
+        let opcode: u16 = 0x00FC;
+        let (mut e, k) = set_up(opcode, Instruction::SCL);
+        let mut s = Screen::new(8, 1);
+        s.set_byte_pixels(0x83, 0, 0);
+        emulate_both(&mut e, opcode, &k, &mut s).unwrap();
+        let c: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+        let z: [u8; 4] = [0, 0, 0, 0];
+        let comparison: Vec<[u8; 4]> = vec![z, z, c, c, z, z, z, z];
+        assert_eq!(s.screen_to_render(), comparison);
+    }
+
+    #[test]
+    fn exit() {
+        let opcode: u16 = 0x00FD;
+        let (mut e, k) = set_up(opcode, Instruction::EXIT);
+        let mut s = Screen::new(1, 1);
+        e.memory[0x200] = 0x00;
+        e.memory[0x201] = 0xFD;
+        e.memory[0x202] = 0x61;
+        e.memory[0x203] = 0x23;
+        run_steps(&mut e, &k, &mut s, 2);
+        assert!(e.has_exited());
+        assert_eq!(e.pc, 0x200);
+        assert_eq!(e.registers[1], 0);
+        e.load_rom(vec![0x61, 0x23]).unwrap();
+        assert!(!e.has_exited());
+    }
+
+    #[test]
+    fn low_and_high() {
+        let (mut e, k) = set_up(0x00FF, Instruction::HIGH);
+        assert_eq!(Instruction::parse_opcode(0x00FE), Instruction::LOW);
+        let mut s = Screen::new(LOW_RES_WIDTH, LOW_RES_HEIGHT);
+        emulate_both(&mut e, 0x00FF, &k, &mut s).unwrap();
+        assert_eq!((s.width(), s.height()), (HIGH_RES_WIDTH, HIGH_RES_HEIGHT));
+        assert_eq!(s.screen_to_render().len(), 128 * 64);
+        emulate_both(&mut e, 0x00FE, &k, &mut s).unwrap();
+        assert_eq!((s.width(), s.height()), (LOW_RES_WIDTH, LOW_RES_HEIGHT));
+        assert_eq!(s.screen_to_render().len(), 64 * 32);
+    }
+
+    #[test]
+    fn vip_hires() {
+        let mut rom = vec![0; 0xC4];
+        // 0x200: JP 0x260, 0x2C0: draw the "0" glyph at (0, 60), clear with 0230
+        rom[..2].copy_from_slice(&[0x12, 0x60]);
+        rom[0xC0..].copy_from_slice(&[0x61, 0x3C, 0xD0, 0x15]);
+        rom.extend_from_slice(&[0x02, 0x30]);
+        let k = Keyboard::new();
+
+        let mut e = Emulator::with_seed(0);
+        let mut s = Screen::new(LOW_RES_WIDTH, LOW_RES_HEIGHT);
+        e.load_rom(rom.clone()).unwrap();
+        run_steps(&mut e, &k, &mut s, 1);
+        assert_eq!(e.pc(), 0x260);
+        assert_eq!(s.resolution(), Some(Resolution::Low));
+
+        e.set_quirks(Quirks::vip_hires());
+        e.load_rom(rom).unwrap();
+        run_steps(&mut e, &k, &mut s, 1);
+        assert_eq!(e.pc(), 0x2C0);
+        assert_eq!(s.resolution(), Some(Resolution::VipHires));
+        run_steps(&mut e, &k, &mut s, 2);
+        assert!(s.is_pixel_on(0, 60) && s.is_pixel_on(3, 63));
+        run_steps(&mut e, &k, &mut s, 1);
+        assert!(!s.is_pixel_on(0, 60));
+    }
+
+    #[test]
+    fn drw_vx_vy_0() {
+        let opcode: u16 = 0xD120;
+        let (mut e, k) = set_up(opcode, Instruction::DRW_Vx_Vy_0);
+        let mut s = Screen::new(16, 16);
+        e.address_register = 0x300;
+        for i in 0..16 {
+            e.memory[0x300 + i * 2] = 0xFF;
+            e.memory[0x300 + i * 2 + 1] = if i % 2 == 0 { 0xFF } else { 0x00 };
+        }
+        e.registers[1] = 0;
+        e.registers[2] = 0;
+        emulate_both(&mut e, opcode, &k, &mut s).unwrap();
+        assert_eq!(e.registers[e.flag_register_index], 0);
+
+        let output = s.screen_to_render();
+        let c: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+        let z: [u8; 4] = [0, 0, 0, 0];
+        for (i, pixel) in output.iter().enumerate() {
+            let (x, y) = (i % 16, i / 16);
+            let expected = if x < 8 || y % 2 == 0 { c } else { z };
+            assert_eq!(*pixel, expected);
+        }
+
+        emulate_both(&mut e, opcode, &k, &mut s).unwrap();
+        assert_eq!(e.registers[e.flag_register_index], 1);
+        assert!(s.screen_to_render().iter().all(|pixel| *pixel == z));
+    }
+
+    #[test]
+    fn ld_hf_vx() {
+        let opcode: u16 = 0xF130;
+        let (mut e, k) = set_up(opcode, Instruction::LD_HF_Vx);
+        e.registers[1] = 0x02;
+        no_screen_test(opcode, &mut e, &k);
+        assert_eq!(e.address_register, 0x50 + 20);
+        assert_eq!(
+            e.memory[e.address_register as usize..e.address_register as usize + 10],
+            LARGE_CHARACTER_SPRITES[20..30]
+        );
+        e.registers[3] = 0x0F;
+        no_screen_test(0xF330, &mut e, &k);
+        assert_eq!(e.address_register, 0x50 + 150);
+    }
+
+    #[test]
+    fn ld_r_vx() {
+        let opcode: u16 = 0xF275;
+        let (mut e, k) = set_up(opcode, Instruction::LD_R_Vx);
+        e.registers[0] = 0x12;
+        e.registers[1] = 0x34;
+        e.registers[2] = 0x56;
+        e.registers[3] = 0x78;
+        no_screen_test(opcode, &mut e, &k);
+        assert_eq!(e.rpl_flags[0], 0x12);
+        assert_eq!(e.rpl_flags[1], 0x34);
+        assert_eq!(e.rpl_flags[2], 0x56);
+        assert_eq!(e.rpl_flags[3], 0);
+    }
+
+    #[test]
+    fn ld_vx_r() {
+        let opcode: u16 = 0xF285;
+        let (mut e, k) = set_up(opcode, Instruction::LD_Vx_R);
+        e.rpl_flags[0] = 0x9A;
+        e.rpl_flags[1] = 0xBC;
+        e.rpl_flags[2] = 0xDE;
+        e.rpl_flags[3] = 0xF0;
+        no_screen_test(opcode, &mut e, &k);
+        assert_eq!(e.registers[0], 0x9A);
+        assert_eq!(e.registers[1], 0xBC);
+        assert_eq!(e.registers[2], 0xDE);
+        assert_eq!(e.registers[3], 0);
+    }
+
+    #[test]
+    fn ld_i_long() {
+        let opcode: u16 = 0xF000;
+        let (mut e, k) = set_up(opcode, Instruction::LD_I_long);
+        let mut s = Screen::new(1, 1);
+        e.load_rom(vec![0xF0, 0x00, 0xAB, 0xCD, 0x61, 0x23]).unwrap();
+        run_steps(&mut e, &k, &mut s, 2);
+        assert_eq!(e.address_register, 0xABCD);
+        assert_eq!(e.registers[1], 0x23);
+        assert_eq!(e.pc, 0x206);
+    }
+
+    #[test]
+    fn skip_over_ld_i_long() {
+        let opcode: u16 = 0x3123;
+        let (mut e, k) = set_up(opcode, Instruction::SE_Vx);
+        e.memory[0x202] = 0xF0;
+        e.memory[0x203] = 0x00;
+        e.registers[1] = 0x23;
+        no_screen_test(opcode, &mut e, &k);
+        assert_eq!(e.pc, (e.program_memory_index + 4) as u16);
+    }
+
+    #[test]
+    fn load_large_rom() {
+        let mut e = Emulator::new();
+        let rom = vec![0xAA; MEMORY_SIZE - 0x200];
+        assert!(e.load_rom(rom).is_ok());
+        assert_eq!(e.memory[MEMORY_SIZE - 1], 0xAA);
+    }
+
+    #[test]
+    fn plane_nibble() {
+        let opcode: u16 = 0xF201;
+        let (mut e, k) = set_up(opcode, Instruction::PLANE_nibble);
+        let mut s = Screen::new(8, 1);
+        e.address_register = 0x300;
+        e.memory[0x300] = 0xFF;
+        e.memory[0x301] = 0xFF;
+        emulate_both(&mut e, opcode, &k, &mut s).unwrap();
+        assert_eq!(s.plane_mask(), 2);
+
+        // only the second plane is selected, its pixels show colour 2
+        emulate_both(&mut e, 0xD001, &k, &mut s).unwrap();
+        assert!((0..8).all(|x| s.color_index(x, 0) == 2));
+        assert_eq!(e.registers[0xF], 0);
+
+        // both planes take a byte of sprite data each, the second plane collides
+        emulate_both(&mut e, 0xF301, &k, &mut s).unwrap();
+        emulate_both(&mut e, 0xD001, &k, &mut s).unwrap();
+        assert!((0..8).all(|x| s.color_index(x, 0) == 1));
+        assert_eq!(e.registers[0xF], 1);
+
+        // CLS only clears the selected plane
+        emulate_both(&mut e, 0xF201, &k, &mut s).unwrap();
+        emulate_both(&mut e, 0x00E0, &k, &mut s).unwrap();
+        assert!(s.screen_to_render().iter().all(|pixel| *pixel == [0xFF; 4]));
+
+        // no planes selected, nothing is drawn
+        emulate_both(&mut e, 0xF001, &k, &mut s).unwrap();
+        emulate_both(&mut e, 0xD001, &k, &mut s).unwrap();
+        assert!((0..8).all(|x| s.color_index(x, 0) == 1));
+        assert_eq!(e.registers[0xF], 0);
+    }
+
+    #[test]
+    fn audio() {
+        let opcode: u16 = 0xF002;
+        let (mut e, k) = set_up(opcode, Instruction::AUDIO);
+        e.address_register = 0x400;
+        for i in 0..16 {
+            e.memory[0x400 + i] = i as u8;
+        }
+        no_screen_test(opcode, &mut e, &k);
+        assert_eq!(
+            e.audio_pattern(),
+            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+        );
+    }
+
+    #[test]
+    fn pitch_vx() {
+        let opcode: u16 = 0xF13A;
+        let (mut e, k) = set_up(opcode, Instruction::PITCH_Vx);
+        assert_eq!(e.audio_playback_rate(), 4000.0);
+        e.registers[1] = 112;
+        no_screen_test(opcode, &mut e, &k);
+        assert_eq!(e.pitch, 112);
+        assert_eq!(e.audio_playback_rate(), 8000.0);
+    }
+
+    #[test]
+    fn save_vx_vy() {
+        let opcode: u16 = 0x5242;
+        let (mut e, k) = set_up(opcode, Instruction::SAVE_Vx_Vy);
+        e.address_register = 0x500;
+        e.registers[2] = 0x22;
+        e.registers[3] = 0x33;
+        e.registers[4] = 0x44;
+        no_screen_test(opcode, &mut e, &k);
+        assert_eq!(e.memory[0x500..0x504], [0x22, 0x33, 0x44, 0]);
+        assert_eq!(e.address_register, 0x500);
+        no_screen_test(0x5422, &mut e, &k);
+        assert_eq!(e.memory[0x500..0x504], [0x44, 0x33, 0x22, 0]);
+    }
+
+    #[test]
+    fn load_vx_vy() {
+        let opcode: u16 = 0x5243;
+        let (mut e, k) = set_up(opcode, Instruction::LOAD_Vx_Vy);
+        e.address_register = 0x500;
+        e.memory[0x500] = 0x22;
+        e.memory[0x501] = 0x33;
+        e.memory[0x502] = 0x44;
+        no_screen_test(opcode, &mut e, &k);
+        assert_eq!(e.registers[1..6], [0, 0x22, 0x33, 0x44, 0]);
+        assert_eq!(e.address_register, 0x500);
+        no_screen_test(0x5423, &mut e, &k);
+        assert_eq!(e.registers[1..6], [0, 0x44, 0x33, 0x22, 0]);
+    }
+
+    fn set_up_quirks(quirks: Quirks) -> (Emulator, Keyboard) {
+        let mut e = Emulator::new();
+        e.set_quirks(quirks);
+        (e, Keyboard::new())
+    }
+
+    #[test]
+    fn quirk_shift_uses_vy() {
+        let (mut e, k) = set_up_quirks(Quirks::default());
+        e.registers[1] = 0x0D;
+        e.registers[2] = 0xF0;
+        no_screen_test(0x8126, &mut e, &k);
+        assert_eq!(e.registers[1], 0x06);
+        assert_eq!(e.registers[e.flag_register_index], 1);
+
+        let quirks = Quirks { shift_uses_vy: true, ..Quirks::default() };
+        let (mut e, k) = set_up_quirks(quirks);
+        e.registers[1] = 0x0D;
+        e.registers[2] = 0xF0;
+        no_screen_test(0x8126, &mut e, &k);
+        assert_eq!(e.registers[1], 0x78);
+        assert_eq!(e.registers[2], 0xF0);
+        assert_eq!(e.registers[e.flag_register_index], 0);
+        e.registers[1] = 0x00;
+        no_screen_test(0x812E, &mut e, &k);
+        assert_eq!(e.registers[1], 0xE0);
+        assert_eq!(e.registers[e.flag_register_index], 1);
+    }
+
+    #[test]
+    fn quirk_load_store_increments_i() {
+        let (mut e, k) = set_up_quirks(Quirks::default());
+        e.address_register = 0x300;
+        no_screen_test(0xF255, &mut e, &k);
+        assert_eq!(e.address_register, 0x300);
+        no_screen_test(0xF265, &mut e, &k);
+        assert_eq!(e.address_register, 0x300);
+
+        let quirks = Quirks { load_store_increments_i: true, ..Quirks::default() };
+        let (mut e, k) = set_up_quirks(quirks);
+        e.address_register = 0x300;
+        e.registers[0] = 0x11;
+        e.registers[1] = 0x22;
+        e.registers[2] = 0x33;
+        no_screen_test(0xF255, &mut e, &k);
+        assert_eq!(e.memory[0x300..0x303], [0x11, 0x22, 0x33]);
+        assert_eq!(e.address_register, 0x303);
+        e.address_register = 0x300;
+        no_screen_test(0xF165, &mut e, &k);
+        assert_eq!(e.address_register, 0x302);
+    }
+
+    #[test]
+    fn quirk_jump_uses_vx() {
+        let (mut e, k) = set_up_quirks(Quirks::default());
+        e.registers[0] = 0x10;
+        e.registers[2] = 0x20;
+        no_screen_test(0xB234, &mut e, &k);
+        assert_eq!(e.pc, 0x244);
+
+        let quirks = Quirks { jump_uses_vx: true, ..Quirks::default() };
+        let (mut e, k) = set_up_quirks(quirks);
+        e.registers[0] = 0x10;
+        e.registers[2] = 0x20;
+        no_screen_test(0xB234, &mut e, &k);
+        assert_eq!(e.pc, 0x254);
+    }
+
+    #[test]
+    fn quirk_logic_resets_vf() {
+        for opcode in [0x8121, 0x8122, 0x8123] {
+            let (mut e, k) = set_up_quirks(Quirks::default());
+            e.registers[e.flag_register_index] = 0x05;
+            no_screen_test(opcode, &mut e, &k);
+            assert_eq!(e.registers[e.flag_register_index], 0x05);
+
+            let quirks = Quirks { logic_resets_vf: true, ..Quirks::default() };
+            let (mut e, k) = set_up_quirks(quirks);
+            e.registers[e.flag_register_index] = 0x05;
+            no_screen_test(opcode, &mut e, &k);
+            assert_eq!(e.registers[e.flag_register_index], 0);
+        }
+    }
+
+    #[test]
+    fn quirk_clip_sprites() {
+        let c: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+        let z: [u8; 4] = [0, 0, 0, 0];
+
+        // wrapped: the sprite drawn at (6, 1) on a 8x2 screen continues on the left and top
+        let (mut e, k) = set_up_quirks(Quirks::default());
+        let mut s = Screen::new(8, 2);
+        e.address_register = 0x300;
+        e.memory[0x300] = 0xF0;
+        e.memory[0x301] = 0xF0;
+        e.registers[1] = 6;
+        e.registers[2] = 1;
+        emulate_both(&mut e, 0xD122, &k, &mut s).unwrap();
+        let comparison: Vec<[u8; 4]> = vec![c, c, z, z, z, z, c, c, c, c, z, z, z, z, c, c];
+        assert_eq!(s.screen_to_render(), comparison);
+
+        // clipped: only the part of the sprite on screen is drawn
+        let quirks = Quirks { clip_sprites: true, ..Quirks::default() };
+        let (mut e, k) = set_up_quirks(quirks);
+        let mut s = Screen::new(8, 2);
+        e.address_register = 0x300;
+        e.memory[0x300] = 0xF0;
+        e.memory[0x301] = 0xF0;
+        e.registers[1] = 6;
+        e.registers[2] = 1;
+        emulate_both(&mut e, 0xD122, &k, &mut s).unwrap();
+        let comparison: Vec<[u8; 4]> = vec![z, z, z, z, z, z, z, z, z, z, z, z, z, z, c, c];
+        assert_eq!(s.screen_to_render(), comparison);
+        assert_eq!(e.registers[e.flag_register_index], 0);
+
+        // the starting position still wraps, (14, 3) is drawn at (6, 1)
+        e.registers[1] = 14;
+        e.registers[2] = 3;
+        emulate_both(&mut e, 0xD122, &k, &mut s).unwrap();
+        assert!(s.screen_to_render().iter().all(|pixel| *pixel == z));
+        assert_eq!(e.registers[e.flag_register_index], 1);
+    }
+
+    #[test]
+    fn quirk_count_collided_rows() {
+        let (mut e, k) = set_up_quirks(Quirks::schip());
+        let mut s = Screen::new(LOW_RES_WIDTH, LOW_RES_HEIGHT);
+        e.address_register = 0x300;
+        e.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+
+        // low res: VF is still 0 or 1
+        e.registers[1] = 0;
+        e.registers[2] = 30;
+        emulate_both(&mut e, 0xD125, &k, &mut s).unwrap();
+        assert_eq!(e.registers[0xF], 0);
+        emulate_both(&mut e, 0xD125, &k, &mut s).unwrap();
+        assert_eq!(e.registers[0xF], 1);
+
+        // hi-res: the 16x16 sprite at y 60 collides on rows 0 and 2, rows 4 to 15 are clipped
+        emulate_both(&mut e, 0x00FF, &k, &mut s).unwrap();
+        e.registers[2] = 60;
+        s.set_pixel(0, 60);
+        s.set_pixel(3, 62);
+        emulate_both(&mut e, 0xD120, &k, &mut s).unwrap();
+        assert_eq!(e.registers[0xF], 14);
+        assert!(!s.is_pixel_on(0, 60) && s.is_pixel_on(0, 61) && s.is_pixel_on(15, 63));
+        emulate_both(&mut e, 0xD122, &k, &mut s).unwrap();
+        assert_eq!(e.registers[0xF], 2);
+
+        // without the quirk a collision is just 1
+        e.set_quirks(Quirks { count_collided_rows: false, ..Quirks::schip() });
+        emulate_both(&mut e, 0xD120, &k, &mut s).unwrap();
+        assert_eq!(e.registers[0xF], 1);
+    }
+
+    #[test]
+    fn invalid_opcode_error() {
+        let (mut e, k) = set_up(0xE1FF, Instruction::Invalid_Instruction);
+        let mut s = Screen::new(1, 1);
+        e.load_rom(vec![0x61, 0x01, 0xE1, 0xFF]).unwrap();
+        run_steps(&mut e, &k, &mut s, 1);
+        let result = e.emulate_step(&k, &mut s);
+        assert_eq!(
+            result,
+            Err(EmulatorError::InvalidOpcode { pc: 0x202, opcode: 0xE1FF })
+        );
+        assert_eq!(e.registers[1], 0x01);
+        assert_eq!(e.pc, 0x202);
+    }
+
+    #[test]
+    fn unimplemented_instruction_error() {
+        let (mut e, k) = set_up(0x0123, Instruction::SYS_addr);
+        let mut s = Screen::new(1, 1);
+        assert_eq!(emulate_both(&mut e, 0x0123, &k, &mut s), Ok(()));
+
+        e.set_quirks(Quirks { sys_unimplemented: true, ..Quirks::default() });
+        let error = EmulatorError::UnimplementedInstruction { pc: 0x200, opcode: 0x0123 };
+        assert_eq!(emulate_both(&mut e, 0x0123, &k, &mut s), Err(error));
+        assert_eq!(error.to_string(), "Unimplemented instruction 0x0123 at 0x0200");
+        assert_eq!(e.pc, 0x200);
+
+        // the VIP hi-res screen clear is still run
+        e.set_quirks(Quirks { sys_unimplemented: true, ..Quirks::vip_hires() });
+        assert_eq!(emulate_both(&mut e, 0x0230, &k, &mut s), Ok(()));
+    }
+
+    #[test]
+    fn stack_overflow_error() {
+        let (mut e, k) = set_up(0x2200, Instruction::CALL_addr);
+        let mut s = Screen::new(1, 1);
+        // 15 nested calls fit, the 16th overflows
+        for _ in 0..15 {
+            emulate_both(&mut e, 0x2200, &k, &mut s).unwrap();
+        }
+        assert_eq!(e.sp, 15);
+        assert_eq!(
+            emulate_both(&mut e, 0x2200, &k, &mut s),
+            Err(EmulatorError::StackOverflow { pc: 0x200, opcode: 0x2200 })
+        );
+        assert_eq!(e.sp, 15);
+    }
+
+    #[test]
+    fn stack_underflow_error() {
+        let (mut e, k) = set_up(0x00EE, Instruction::RET);
+        let mut s = Screen::new(1, 1);
+        assert_eq!(
+            emulate_both(&mut e, 0x00EE, &k, &mut s),
+            Err(EmulatorError::StackUnderflow { pc: 0x200, opcode: 0x00EE })
+        );
+        assert_eq!(e.sp, 0);
+        assert_eq!(e.pc, 0x200);
+    }
+
+    #[test]
+    fn memory_out_of_range_error() {
+        let (mut e, k) = set_up(0xF233, Instruction::LD_B_Vx);
+        let mut s = Screen::new(8, 8);
+        e.address_register = 0xFFFE;
+        let error = EmulatorError::MemoryOutOfRange { pc: 0x200, opcode: 0xF233, address: 0xFFFE };
+        assert_eq!(emulate_both(&mut e, 0xF233, &k, &mut s), Err(error));
+
+        let error = EmulatorError::MemoryOutOfRange { pc: 0x200, opcode: 0xF255, address: 0xFFFE };
+        assert_eq!(emulate_both(&mut e, 0xF255, &k, &mut s), Err(error));
+        assert_eq!(emulate_both(&mut e, 0xF155, &k, &mut s), Ok(()));
+
+        let error = EmulatorError::MemoryOutOfRange { pc: 0x200, opcode: 0xF265, address: 0xFFFE };
+        assert_eq!(emulate_both(&mut e, 0xF265, &k, &mut s), Err(error));
+
+        let error = EmulatorError::MemoryOutOfRange { pc: 0x200, opcode: 0xD123, address: 0xFFFE };
+        assert_eq!(emulate_both(&mut e, 0xD123, &k, &mut s), Err(error));
+        assert_eq!(error.pc(), 0x200);
+        assert_eq!(error.opcode(), 0xD123);
+    }
+
+    #[test]
+    fn run_frame_ticks_timers_once() {
+        let mut e = Emulator::new();
+        let k = Keyboard::new();
+        let mut s = Screen::new(1, 1);
+        // 0x200: V1 += 1, jump back to 0x200
+        e.load_rom(vec![0x71, 0x01, 0x12, 0x00]).unwrap();
+        e.delay_timer_register = 10;
+        e.sound_timer_register = 1;
+        e.run_frame(&k, &mut s).unwrap();
+        assert_eq!(e.delay_timer(), 9);
+        assert_eq!(e.sound_timer(), 0);
+        assert_eq!(e.registers[1], 5);
+        e.run_frame(&k, &mut s).unwrap();
+        assert_eq!(e.delay_timer(), 8);
+        assert_eq!(e.sound_timer(), 0);
+        assert_eq!(e.registers[1], 9);
+    }
+
+    #[test]
+    fn instructions_per_frame() {
+        let mut e = Emulator::new();
+        let k = Keyboard::new();
+        let mut s = Screen::new(1, 1);
+        e.load_rom(vec![0x71, 0x01, 0x12, 0x00]).unwrap();
+        assert_eq!(e.instructions_per_frame(), DEFAULT_INSTRUCTIONS_PER_FRAME);
+        e.set_instructions_per_frame(20);
+        e.run_frame(&k, &mut s).unwrap();
+        assert_eq!(e.registers[1], 10);
+    }
+
+    #[test]
+    fn run_frame_waits_for_key() {
+        let mut e = Emulator::new();
+        let mut k = Keyboard::new();
+        let mut s = Screen::new(1, 1);
+        // 0x200: wait for key into V1, then V2 = 0x55
+        e.load_rom(vec![0xF1, 0x0A, 0x62, 0x55]).unwrap();
+        e.delay_timer_register = 3;
+        e.run_frame(&k, &mut s).unwrap();
+        assert_eq!(e.pc, 0x200);
+        assert_eq!(e.delay_timer(), 2);
+        k.set_key(7, Key::Down);
+        e.run_frame(&k, &mut s).unwrap();
+        assert_eq!(e.registers[1], 7);
+        assert_eq!(e.registers[2], 0x55);
+        assert_eq!(e.delay_timer(), 1);
+    }
+
+    #[test]
+    fn paused_frame_does_nothing() {
+        let mut e = Emulator::new();
+        let k = Keyboard::new();
+        let mut s = Screen::new(1, 1);
+        e.load_rom(vec![0x71, 0x01, 0x12, 0x00]).unwrap();
+        e.delay_timer_register = 3;
+        e.pause();
+        e.run_frame(&k, &mut s).unwrap();
+        assert_eq!(e.registers[1], 0);
+        assert_eq!(e.delay_timer(), 3);
+        e.unpause();
+        e.run_frame(&k, &mut s).unwrap();
+        assert_eq!(e.delay_timer(), 2);
+    }
+
+    // Random x position, walking y, delay timer set from the random value.
+    const SAVE_STATE_ROM: [u8; 12] = [0xC0, 0xFF, 0xA0, 0x00, 0xD0, 0x15, 0x71, 0x01, 0xF0, 0x15, 0x12, 0x00];
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut e = Emulator::with_seed(42);
+        let mut k = Keyboard::new();
+        let mut s = Screen::new(LOW_RES_WIDTH, LOW_RES_HEIGHT);
+        e.load_rom(SAVE_STATE_ROM.to_vec()).unwrap();
+        for _ in 0..10 {
+            e.run_frame(&k, &mut s).unwrap();
+        }
+        k.set_key(3, Key::Down);
+        let state = e.save_state(&k, &s);
+
+        let mut restored = Emulator::with_seed(7);
+        let mut restored_k = Keyboard::new();
+        let mut restored_s = Screen::new(1, 1);
+        restored.load_state(&state, &mut restored_k, &mut restored_s).unwrap();
+        assert_eq!(restored.save_state(&restored_k, &restored_s), state);
+        assert!(restored_k.is_key_down(3));
+        assert_eq!(restored.seed(), 42);
+
+        // execution after the restore matches execution without one
+        for _ in 0..20 {
+            e.run_frame(&k, &mut s).unwrap();
+            restored.run_frame(&restored_k, &mut restored_s).unwrap();
+        }
+        assert_eq!(restored.registers, e.registers);
+        assert_eq!(restored.pc, e.pc);
+        assert_eq!(restored.delay_timer(), e.delay_timer());
+        assert_eq!(restored_s.screen_to_render(), s.screen_to_render());
+        assert_eq!(restored.save_state(&restored_k, &restored_s), e.save_state(&k, &s));
+    }
+
+    #[test]
+    fn load_bad_state_changes_nothing() {
+        let mut e = Emulator::with_seed(42);
+        let mut k = Keyboard::new();
+        let mut s = Screen::new(LOW_RES_WIDTH, LOW_RES_HEIGHT);
+        e.load_rom(SAVE_STATE_ROM.to_vec()).unwrap();
+        e.run_frame(&k, &mut s).unwrap();
+        let before = e.save_state(&k, &s);
+
+        let mut truncated = before.clone();
+        truncated.pop();
+        assert_eq!(e.load_state(&truncated, &mut k, &mut s), Err(SaveStateError::Truncated));
+        let mut too_long = before.clone();
+        too_long.push(0);
+        assert!(e.load_state(&too_long, &mut k, &mut s).is_err());
+        assert_eq!(e.load_state(b"garbage", &mut k, &mut s), Err(SaveStateError::NotASaveState));
+        assert_eq!(e.save_state(&k, &s), before);
+    }
+
+    #[test]
+    fn set_memory_invalidates_cached_instruction() {
+        let mut e = Emulator::with_seed(0);
+        let k = Keyboard::new();
+        let mut s = Screen::new(1, 1);
+        // 0x200: V0 = 1, jump to 0x200
+        e.load_rom(vec![0x60, 0x01, 0x12, 0x00]).unwrap();
+        run_steps(&mut e, &k, &mut s, 2);
+        assert_eq!(e.registers()[0], 1);
+        // only the second byte of the cached LD V0 changes
+        e.set_memory(0x02, 0x201);
+        run_steps(&mut e, &k, &mut s, 1);
+        assert_eq!(e.registers()[0], 2);
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        let mut e = Emulator::with_seed(0);
+        let k = Keyboard::new();
+        let mut s = Screen::new(1, 1);
+        // 0x200: V2 += 1, I = 0x200, V0 = 0x72, V1 = 0x10, store V0-V1 at I so 0x200
+        // becomes V2 += 0x10, jump to 0x200
+        e.load_rom(vec![0x72, 0x01, 0xA2, 0x00, 0x60, 0x72, 0x61, 0x10, 0xF1, 0x55, 0x12, 0x00])
+            .unwrap();
+        run_steps(&mut e, &k, &mut s, 6);
+        assert_eq!(e.registers()[2], 0x01);
+        run_steps(&mut e, &k, &mut s, 1);
+        assert_eq!(e.registers()[2], 0x11);
+
+        // the BCD of V3 = 5 written from 0x201 turns 0x200 into V2 += 0 and 0x202 into SYS 005
+        e.load_rom(vec![0x72, 0x01, 0xA2, 0x01, 0x63, 0x05, 0xF3, 0x33, 0x12, 0x00]).unwrap();
+        run_steps(&mut e, &k, &mut s, 6);
+        assert_eq!(e.registers()[2], 0x01);
+        e.address_register = 0;
+        run_steps(&mut e, &k, &mut s, 1);
+        assert_eq!(e.address_register(), 0);
+    }
 }
@@ -0,0 +1,80 @@
+use std::{error::Error, fmt};
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::JsValue;
+
+// Errors raised while executing a rom. Every variant carries the address of the
+// instruction that failed and its opcode, so the frontend can report where a rom went wrong.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmulatorError {
+    // The opcode does not decode to any known instruction.
+    InvalidOpcode { pc: u16, opcode: u16 },
+    // The instruction decoded, but the emulator cannot run it.
+    UnimplementedInstruction { pc: u16, opcode: u16 },
+    // CALL with 15 calls already nested, stack[0] is never used.
+    StackOverflow { pc: u16, opcode: u16 },
+    // RET with nothing on the stack.
+    StackUnderflow { pc: u16, opcode: u16 },
+    // An instruction tried to read or write memory past the end of the address space,
+    // address is the first byte that was out of range.
+    MemoryOutOfRange { pc: u16, opcode: u16, address: usize },
+}
+
+impl EmulatorError {
+    pub fn pc(&self) -> u16 {
+        match *self {
+            EmulatorError::InvalidOpcode { pc, .. }
+            | EmulatorError::UnimplementedInstruction { pc, .. }
+            | EmulatorError::StackOverflow { pc, .. }
+            | EmulatorError::StackUnderflow { pc, .. }
+            | EmulatorError::MemoryOutOfRange { pc, .. } => pc,
+        }
+    }
+
+    pub fn opcode(&self) -> u16 {
+        match *self {
+            EmulatorError::InvalidOpcode { opcode, .. }
+            | EmulatorError::UnimplementedInstruction { opcode, .. }
+            | EmulatorError::StackOverflow { opcode, .. }
+            | EmulatorError::StackUnderflow { opcode, .. }
+            | EmulatorError::MemoryOutOfRange { opcode, .. } => opcode,
+        }
+    }
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulatorError::InvalidOpcode { pc, opcode } => {
+                write!(f, "Invalid opcode {:#06x} at {:#06x}", opcode, pc)
+            }
+            EmulatorError::UnimplementedInstruction { pc, opcode } => {
+                write!(f, "Unimplemented instruction {:#06x} at {:#06x}", opcode, pc)
+            }
+            EmulatorError::StackOverflow { pc, opcode } => {
+                write!(f, "Stack overflow from {:#06x} at {:#06x}", opcode, pc)
+            }
+            EmulatorError::StackUnderflow { pc, opcode } => {
+                write!(f, "Stack underflow from {:#06x} at {:#06x}", opcode, pc)
+            }
+            EmulatorError::MemoryOutOfRange {
+                pc,
+                opcode,
+                address,
+            } => write!(
+                f,
+                "Memory access out of range ({:#06x}) from {:#06x} at {:#06x}",
+                address, opcode, pc
+            ),
+        }
+    }
+}
+
+impl Error for EmulatorError {}
+
+#[cfg(target_arch = "wasm32")]
+impl From<EmulatorError> for JsValue {
+    fn from(error: EmulatorError) -> JsValue {
+        JsValue::from_str(&error.to_string())
+    }
+}
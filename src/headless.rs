@@ -0,0 +1,217 @@
+use crate::{
+    emulator::{Emulator, LOW_RES_HEIGHT, LOW_RES_WIDTH, MEMORY_SIZE},
+    error::EmulatorError,
+    keyboard::{Key, Keyboard},
+    palette::Palette,
+    phosphor::PhosphorFilter,
+    screen::Screen,
+    screenshot::{save_image, ScreenshotError},
+};
+use std::path::Path;
+
+// Run roms without a window, e.g. for test roms and regression checks on machines with no display.
+
+// A key press or release, applied before the given frame runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Input {
+    pub frame: u32,
+    pub key: u8,
+    pub state: Key,
+}
+
+// Everything left behind once a headless run stops.
+pub struct FinalState {
+    pub emulator: Emulator,
+    pub screen: Screen,
+    pub keyboard: Keyboard,
+    // Frames that ran to completion, fewer than requested if the rom exited or hit an error.
+    pub frames_run: u32,
+    // The error that stopped the run early, if any.
+    pub error: Option<EmulatorError>,
+    // The display filter the run was given, updated every frame.
+    pub phosphor: Option<PhosphorFilter>,
+}
+
+impl FinalState {
+    // The screen as text, one line per row, '#' for pixels that are on and '.' for pixels that are off.
+    pub fn screen_text(&self) -> String {
+        let mut text = String::with_capacity(((self.screen.width() + 1) * self.screen.height()) as usize);
+        for y in 0..self.screen.height() {
+            for x in 0..self.screen.width() {
+                text.push(if self.screen.is_pixel_on(x, y) { '#' } else { '.' });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // The final image as RGBA, row by row, through the phosphor filter if the run had one.
+    pub fn rgba(&self) -> Vec<u8> {
+        let mut buffer = vec![0; (self.screen.width() * self.screen.height() * 4) as usize];
+        match &self.phosphor {
+            Some(filter) => filter.render_into(&mut buffer),
+            None => self.screen.render_into(&mut buffer),
+        }
+        buffer
+    }
+
+    // Write rgba() to a .png or .ppm file, every pixel scale x scale pixels large.
+    pub fn save_screenshot(&self, path: &Path, scale: u32) -> Result<(), ScreenshotError> {
+        save_image(&self.rgba(), self.screen.width(), self.screen.height(), path, scale)
+    }
+}
+
+// Run rom for the given number of 60Hz frames with a fixed seed, so the same rom and inputs
+// always produce the same final state.
+pub fn run(rom: &[u8], frames: u32, inputs: &[Input]) -> Result<FinalState, String> {
+    run_with(Emulator::with_seed(0), rom, frames, inputs)
+}
+
+// Same as run, with an emulator that has already been configured (quirks, seed, speed).
+pub fn run_with(emulator: Emulator, rom: &[u8], frames: u32, inputs: &[Input]) -> Result<FinalState, String> {
+    run_filtered(emulator, rom, frames, inputs, Palette::default(), None)
+}
+
+// Same as run_with, drawing the screen in palette and passing every frame through the phosphor filter.
+pub fn run_filtered(
+    mut emulator: Emulator,
+    rom: &[u8],
+    frames: u32,
+    inputs: &[Input],
+    palette: Palette,
+    mut phosphor: Option<PhosphorFilter>,
+) -> Result<FinalState, String> {
+    if rom.len() > MEMORY_SIZE - emulator.program_start_address() {
+        return Err(String::from("Rom length greater than memory size."));
+    }
+    if let Some(input) = inputs.iter().find(|input| input.key > 15) {
+        return Err(format!("Invalid key {:#x}, only keys 0-F exist", input.key));
+    }
+    let _ = emulator.load_rom(rom.to_vec());
+    let mut screen = Screen::new(LOW_RES_WIDTH, LOW_RES_HEIGHT);
+    screen.set_palette(palette);
+    let mut keyboard = Keyboard::new();
+    let mut frames_run = 0;
+    let mut error = None;
+
+    for frame in 0..frames {
+        if emulator.has_exited() {
+            break;
+        }
+        for input in inputs.iter().filter(|input| input.frame == frame) {
+            keyboard.set_key(input.key, input.state);
+        }
+        if let Err(err) = emulator.run_frame(&keyboard, &mut screen) {
+            error = Some(err);
+            break;
+        }
+        if let Some(filter) = phosphor.as_mut() {
+            filter.update(&screen);
+        }
+        frames_run += 1;
+    }
+
+    Ok(FinalState {
+        emulator,
+        screen,
+        keyboard,
+        frames_run,
+        error,
+        phosphor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_font_sprite() {
+        // 0x200: V0 = 2, V1 = 1, I = sprite for V0, draw 5 rows at (V1, V1), loop forever
+        let rom = [0x60, 0x02, 0x61, 0x01, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x08];
+        let state = run(&rom, 3, &[]).unwrap();
+        assert_eq!(state.frames_run, 3);
+        assert_eq!(state.error, None);
+        let text = state.screen_text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert_eq!(&lines[1][..6], ".####.");
+        assert_eq!(&lines[2][..6], "....#.");
+        assert_eq!(&lines[3][..6], ".####.");
+        assert_eq!(&lines[4][..6], ".#....");
+        assert_eq!(&lines[5][..6], ".####.");
+    }
+
+    #[test]
+    fn inputs_applied_on_frame() {
+        // 0x200: wait for a key into V5, then loop forever
+        let rom = [0xF5, 0x0A, 0x12, 0x02];
+        let inputs = [Input { frame: 4, key: 0xB, state: Key::Down }];
+        let state = run(&rom, 3, &inputs).unwrap();
+        assert_eq!(state.emulator.pc(), 0x200);
+        let state = run(&rom, 5, &inputs).unwrap();
+        assert_eq!(state.emulator.pc(), 0x202);
+        assert!(state.keyboard.is_key_down(0xB));
+    }
+
+    #[test]
+    fn stops_on_error() {
+        let state = run(&[0xFF, 0xFF], 10, &[]).unwrap();
+        assert_eq!(state.frames_run, 0);
+        assert_eq!(state.error, Some(EmulatorError::InvalidOpcode { pc: 0x200, opcode: 0xFFFF }));
+    }
+
+    #[test]
+    fn phosphor_image() {
+        // 0x200: I = font sprite 0, then once a frame draw it at (0, 0), erasing the last draw:
+        // draw, DT = 1, wait until DT is 0, repeat
+        let rom = [
+            0xA0, 0x00, 0xD0, 0x05, 0x61, 0x01, 0xF1, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x08, 0x12, 0x02,
+        ];
+        let plain = run(&rom, 2, &[]).unwrap();
+        assert!(!plain.screen.is_pixel_on(0, 0));
+        assert!(plain.rgba()[..4].iter().all(|byte| *byte == 0));
+
+        let filtered = run_filtered(Emulator::with_seed(0), &rom, 2, &[], Palette::default(), Some(PhosphorFilter::new(0.5))).unwrap();
+        assert!(!filtered.screen.is_pixel_on(0, 0));
+        assert_eq!(filtered.rgba()[..4], [0x80; 4]);
+    }
+
+    #[test]
+    fn screenshot() {
+        let rom = [0x60, 0x02, 0x61, 0x01, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x08];
+        let state = run(&rom, 1, &[]).unwrap();
+        let path = std::env::temp_dir().join(format!("chip8-headless-test-{}.ppm", std::process::id()));
+        state.save_screenshot(&path, 2).unwrap();
+        let ppm = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let header = b"P6\n128 64\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        // the top left of the 2 drawn at (1, 1) is at (2, 2) and (3, 3) when scaled
+        let pixel = |x: usize, y: usize| &ppm[header.len() + (y * 128 + x) * 3..][..3];
+        assert_eq!(pixel(1, 1), [0, 0, 0]);
+        assert_eq!(pixel(2, 2), [0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixel(3, 3), [0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn screenshot_uses_palette() {
+        let rom = [0x60, 0x02, 0x61, 0x01, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x08];
+        let state = run_filtered(Emulator::with_seed(0), &rom, 1, &[], Palette::amber(), None).unwrap();
+        let path = std::env::temp_dir().join(format!("chip8-headless-palette-{}.ppm", std::process::id()));
+        state.save_screenshot(&path, 1).unwrap();
+        let ppm = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let header = b"P6\n64 32\n255\n";
+        let pixel = |x: usize, y: usize| &ppm[header.len() + (y * 64 + x) * 3..][..3];
+        // (1, 1) is the top left of the 2, (0, 0) is background
+        assert_eq!(pixel(1, 1), &Palette::amber().colors[1][..3]);
+        assert_eq!(pixel(0, 0), &Palette::amber().colors[0][..3]);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(run(&vec![0; MEMORY_SIZE], 1, &[]).is_err());
+        assert!(run(&[0x12, 0x00], 1, &[Input { frame: 0, key: 16, state: Key::Down }]).is_err());
+    }
+}
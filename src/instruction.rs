@@ -1,5 +1,7 @@
+use std::sync::OnceLock;
+
 #[allow(non_camel_case_types)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
     SYS_addr,
     CLS,
@@ -47,10 +49,16 @@ pub enum Instruction {
     LD_HF_Vx,
     LD_R_Vx,
     LD_Vx_R,
+    // XO-CHIP instructions
+    LD_I_long,
+    PLANE_nibble,
+    AUDIO,
+    PITCH_Vx,
+    SAVE_Vx_Vy,
+    LOAD_Vx_Vy,
     Invalid_Instruction,
 }
 
-// TODO add function to translate instructions into opcodes to allow for writing new programs
 impl Instruction {
     pub fn parse_opcode(opcode: u16) -> Instruction {
         let high_byte: u8 = ((opcode >> 8) & 0xFF) as u8;
@@ -80,9 +88,9 @@ impl Instruction {
             return Instruction::HIGH;
         }
         // Super chip-48 instruction
-        else if first_nibble == 0x00 && third_nibble == 0xE0 {
+        else if high_byte == 0x00 && low_byte == 0xE0 {
             return Instruction::CLS;
-        } else if first_nibble == 0x00 && third_nibble == 0xC0 {
+        } else if high_byte == 0x00 && third_nibble == 0xC0 {
             return Instruction::SCD_nibble;
         }
         // Super chip-48 instruction
@@ -96,7 +104,13 @@ impl Instruction {
             return Instruction::SE_Vx;
         } else if first_nibble == 0x40 {
             return Instruction::SNE_Vx;
-        } else if first_nibble == 0x50 {
+        }
+        // XO-CHIP instructions
+        else if first_nibble == 0x50 && last_nibble == 0x02 {
+            return Instruction::SAVE_Vx_Vy;
+        } else if first_nibble == 0x50 && last_nibble == 0x03 {
+            return Instruction::LOAD_Vx_Vy;
+        } else if first_nibble == 0x50 && last_nibble == 0x00 {
             return Instruction::SE_Vx_Vy;
         } else if first_nibble == 0x60 {
             return Instruction::LD_Vx;
@@ -164,13 +178,170 @@ impl Instruction {
             return Instruction::LD_R_Vx;
         } else if first_nibble == 0xF0 && low_byte == 0x85 {
             return Instruction::LD_Vx_R;
+        }
+        // XO-CHIP instructions
+        else if high_byte == 0xF0 && low_byte == 0x00 {
+            return Instruction::LD_I_long;
+        } else if first_nibble == 0xF0 && low_byte == 0x01 {
+            return Instruction::PLANE_nibble;
+        } else if high_byte == 0xF0 && low_byte == 0x02 {
+            return Instruction::AUDIO;
+        } else if first_nibble == 0xF0 && low_byte == 0x3A {
+            return Instruction::PITCH_Vx;
         } else {
             return Instruction::Invalid_Instruction;
         }
     }
 }
 
-// TODO write more tests cases for this
+// Operand types, so a register number can not be passed where a byte or an address is expected.
+// Register V0-VF, the x or y nibble of an opcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Register(pub u8);
+// 4 bit value, the last nibble of an opcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Nibble(pub u8);
+// 8 bit value, the low byte of an opcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Byte(pub u8);
+// 12 bit address, the low 3 nibbles of an opcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Address(pub u16);
+
+impl Register {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// An instruction together with every operand field of its opcode. decode() fills in all of the
+// fields, each instruction only reads the ones that are part of it (e.g. LD_Vx uses x and kk).
+// XO-CHIP's LD_I_long address is in the word after the opcode and is not part of this.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodedInstruction {
+    pub instruction: Instruction,
+    pub x: Register,
+    pub y: Register,
+    pub n: Nibble,
+    pub kk: Byte,
+    pub nnn: Address,
+}
+
+impl DecodedInstruction {
+    // An instruction with every operand set to 0.
+    pub fn new(instruction: Instruction) -> DecodedInstruction {
+        DecodedInstruction {
+            instruction,
+            x: Register(0),
+            y: Register(0),
+            n: Nibble(0),
+            kk: Byte(0),
+            nnn: Address(0),
+        }
+    }
+}
+
+// parse_opcode's result for every possible opcode, built on first use and shared by every emulator.
+// A lookup is a single index instead of walking the if/else chain.
+static INSTRUCTION_TABLE: OnceLock<Box<[Instruction]>> = OnceLock::new();
+
+fn instruction_table() -> &'static [Instruction] {
+    INSTRUCTION_TABLE.get_or_init(|| (0..=0xFFFFu16).map(Instruction::parse_opcode).collect())
+}
+
+impl Instruction {
+    // Same as parse_opcode, read from the precomputed table.
+    pub fn lookup(opcode: u16) -> Instruction {
+        instruction_table()[opcode as usize]
+    }
+}
+
+pub fn decode(opcode: u16) -> DecodedInstruction {
+    DecodedInstruction {
+        instruction: Instruction::lookup(opcode),
+        x: Register(((opcode >> 8) & 0xF) as u8),
+        y: Register(((opcode >> 4) & 0xF) as u8),
+        n: Nibble((opcode & 0xF) as u8),
+        kk: Byte((opcode & 0xFF) as u8),
+        nnn: Address(opcode & 0xFFF),
+    }
+}
+
+impl Instruction {
+    // The opcode for this instruction, built from only the operands it uses, the rest are ignored.
+    // Returns None for Invalid_Instruction, which has no encoding.
+    pub fn to_opcode(self, x: Register, y: Register, n: Nibble, kk: Byte, nnn: Address) -> Option<u16> {
+        let x = ((x.0 & 0xF) as u16) << 8;
+        let y = ((y.0 & 0xF) as u16) << 4;
+        let n = (n.0 & 0xF) as u16;
+        let kk = kk.0 as u16;
+        let nnn = nnn.0 & 0xFFF;
+        let opcode = match self {
+            Instruction::SYS_addr => nnn,
+            Instruction::CLS => 0x00E0,
+            Instruction::RET => 0x00EE,
+            Instruction::JP_addr => 0x1000 | nnn,
+            Instruction::CALL_addr => 0x2000 | nnn,
+            Instruction::SE_Vx => 0x3000 | x | kk,
+            Instruction::SNE_Vx => 0x4000 | x | kk,
+            Instruction::SE_Vx_Vy => 0x5000 | x | y,
+            Instruction::LD_Vx => 0x6000 | x | kk,
+            Instruction::ADD_Vx => 0x7000 | x | kk,
+            Instruction::LD_Vx_Vy => 0x8000 | x | y,
+            Instruction::OR_Vx_Vy => 0x8001 | x | y,
+            Instruction::AND_Vx_Vy => 0x8002 | x | y,
+            Instruction::XOR_Vx_Vy => 0x8003 | x | y,
+            Instruction::ADD_Vx_Vy => 0x8004 | x | y,
+            Instruction::SUB_Vx_Vy => 0x8005 | x | y,
+            Instruction::SHR_Vx => 0x8006 | x | y,
+            Instruction::SUBN_Vx_Vy => 0x8007 | x | y,
+            Instruction::SHL_Vx => 0x800E | x | y,
+            Instruction::SNE_Vx_Vy => 0x9000 | x | y,
+            Instruction::LD_I => 0xA000 | nnn,
+            Instruction::JP_V0 => 0xB000 | nnn,
+            Instruction::RND_Vx => 0xC000 | x | kk,
+            Instruction::DRW_Vx_Vy => 0xD000 | x | y | n,
+            Instruction::SKP_Vx => 0xE09E | x,
+            Instruction::SKNP_Vx => 0xE0A1 | x,
+            Instruction::LD_Vx_DT => 0xF007 | x,
+            Instruction::LD_Vx_K => 0xF00A | x,
+            Instruction::LD_DT_Vx => 0xF015 | x,
+            Instruction::LD_ST_Vx => 0xF018 | x,
+            Instruction::ADD_I_Vx => 0xF01E | x,
+            Instruction::LD_F_Vx => 0xF029 | x,
+            Instruction::LD_B_Vx => 0xF033 | x,
+            Instruction::LD_I_Vx => 0xF055 | x,
+            Instruction::LD_Vx_I => 0xF065 | x,
+            Instruction::SCD_nibble => 0x00C0 | n,
+            Instruction::SCR => 0x00FB,
+            Instruction::SCL => 0x00FC,
+            Instruction::EXIT => 0x00FD,
+            Instruction::LOW => 0x00FE,
+            Instruction::HIGH => 0x00FF,
+            Instruction::DRW_Vx_Vy_0 => 0xD000 | x | y,
+            Instruction::LD_HF_Vx => 0xF030 | x,
+            Instruction::LD_R_Vx => 0xF075 | x,
+            Instruction::LD_Vx_R => 0xF085 | x,
+            Instruction::LD_I_long => 0xF000,
+            // the plane mask is stored where x normally is
+            Instruction::PLANE_nibble => 0xF001 | x,
+            Instruction::AUDIO => 0xF002,
+            Instruction::PITCH_Vx => 0xF03A | x,
+            Instruction::SAVE_Vx_Vy => 0x5002 | x | y,
+            Instruction::LOAD_Vx_Vy => 0x5003 | x | y,
+            Instruction::Invalid_Instruction => return None,
+        };
+        Some(opcode)
+    }
+}
+
+// The opcode for a decoded instruction, encode(&decode(opcode)) == Some(opcode) for every valid opcode.
+pub fn encode(decoded: &DecodedInstruction) -> Option<u16> {
+    decoded
+        .instruction
+        .to_opcode(decoded.x, decoded.y, decoded.n, decoded.kk, decoded.nnn)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -181,4 +352,143 @@ mod tests {
         let i = Instruction::parse_opcode(opcode);
         assert_eq!(i, Instruction::RET);
     }
+
+    #[test]
+    fn xo_chip_instructions() {
+        assert_eq!(Instruction::parse_opcode(0xF000), Instruction::LD_I_long);
+        assert_eq!(Instruction::parse_opcode(0xF201), Instruction::PLANE_nibble);
+        assert_eq!(Instruction::parse_opcode(0xF002), Instruction::AUDIO);
+        assert_eq!(Instruction::parse_opcode(0xF13A), Instruction::PITCH_Vx);
+        assert_eq!(Instruction::parse_opcode(0x5122), Instruction::SAVE_Vx_Vy);
+        assert_eq!(Instruction::parse_opcode(0x5123), Instruction::LOAD_Vx_Vy);
+        assert_eq!(Instruction::parse_opcode(0x5120), Instruction::SE_Vx_Vy);
+        assert_eq!(Instruction::parse_opcode(0xF100), Instruction::Invalid_Instruction);
+    }
+
+    #[test]
+    fn decode_operands() {
+        let d = decode(0xD12A);
+        assert_eq!(d.instruction, Instruction::DRW_Vx_Vy);
+        assert_eq!(d.x, Register(1));
+        assert_eq!(d.y, Register(2));
+        assert_eq!(d.n, Nibble(0xA));
+        assert_eq!(d.kk, Byte(0x2A));
+        assert_eq!(d.nnn, Address(0x12A));
+    }
+
+    #[test]
+    fn encode_uses_only_instruction_operands() {
+        for opcode in [0x00E0, 0x1234, 0x3A55, 0x5AB2, 0x8AB6, 0xD125, 0xF329, 0xF201, 0xF000] {
+            assert_eq!(encode(&decode(opcode)), Some(opcode));
+        }
+        let ld_vx = DecodedInstruction {
+            x: Register(3),
+            y: Register(7),
+            kk: Byte(0x42),
+            ..DecodedInstruction::new(Instruction::LD_Vx)
+        };
+        assert_eq!(encode(&ld_vx), Some(0x6342));
+        assert_eq!(encode(&decode(0xE1FF)), None);
+    }
+
+    // Every instruction's opcode pattern, written independently of parse_opcode. The first
+    // matching (mask, value) wins, so exact patterns come before the catch all SYS 0nnn.
+    const PATTERNS: [(u16, u16, Instruction); 51] = [
+        (0xFFFF, 0x00E0, Instruction::CLS),
+        (0xFFFF, 0x00EE, Instruction::RET),
+        (0xFFF0, 0x00C0, Instruction::SCD_nibble),
+        (0xFFFF, 0x00FB, Instruction::SCR),
+        (0xFFFF, 0x00FC, Instruction::SCL),
+        (0xFFFF, 0x00FD, Instruction::EXIT),
+        (0xFFFF, 0x00FE, Instruction::LOW),
+        (0xFFFF, 0x00FF, Instruction::HIGH),
+        (0xF000, 0x0000, Instruction::SYS_addr),
+        (0xF000, 0x1000, Instruction::JP_addr),
+        (0xF000, 0x2000, Instruction::CALL_addr),
+        (0xF000, 0x3000, Instruction::SE_Vx),
+        (0xF000, 0x4000, Instruction::SNE_Vx),
+        (0xF00F, 0x5000, Instruction::SE_Vx_Vy),
+        (0xF00F, 0x5002, Instruction::SAVE_Vx_Vy),
+        (0xF00F, 0x5003, Instruction::LOAD_Vx_Vy),
+        (0xF000, 0x6000, Instruction::LD_Vx),
+        (0xF000, 0x7000, Instruction::ADD_Vx),
+        (0xF00F, 0x8000, Instruction::LD_Vx_Vy),
+        (0xF00F, 0x8001, Instruction::OR_Vx_Vy),
+        (0xF00F, 0x8002, Instruction::AND_Vx_Vy),
+        (0xF00F, 0x8003, Instruction::XOR_Vx_Vy),
+        (0xF00F, 0x8004, Instruction::ADD_Vx_Vy),
+        (0xF00F, 0x8005, Instruction::SUB_Vx_Vy),
+        (0xF00F, 0x8006, Instruction::SHR_Vx),
+        (0xF00F, 0x8007, Instruction::SUBN_Vx_Vy),
+        (0xF00F, 0x800E, Instruction::SHL_Vx),
+        (0xF00F, 0x9000, Instruction::SNE_Vx_Vy),
+        (0xF000, 0xA000, Instruction::LD_I),
+        (0xF000, 0xB000, Instruction::JP_V0),
+        (0xF000, 0xC000, Instruction::RND_Vx),
+        (0xF00F, 0xD000, Instruction::DRW_Vx_Vy_0),
+        (0xF000, 0xD000, Instruction::DRW_Vx_Vy),
+        (0xF0FF, 0xE09E, Instruction::SKP_Vx),
+        (0xF0FF, 0xE0A1, Instruction::SKNP_Vx),
+        (0xFFFF, 0xF000, Instruction::LD_I_long),
+        (0xF0FF, 0xF001, Instruction::PLANE_nibble),
+        (0xFFFF, 0xF002, Instruction::AUDIO),
+        (0xF0FF, 0xF007, Instruction::LD_Vx_DT),
+        (0xF0FF, 0xF00A, Instruction::LD_Vx_K),
+        (0xF0FF, 0xF015, Instruction::LD_DT_Vx),
+        (0xF0FF, 0xF018, Instruction::LD_ST_Vx),
+        (0xF0FF, 0xF01E, Instruction::ADD_I_Vx),
+        (0xF0FF, 0xF029, Instruction::LD_F_Vx),
+        (0xF0FF, 0xF030, Instruction::LD_HF_Vx),
+        (0xF0FF, 0xF033, Instruction::LD_B_Vx),
+        (0xF0FF, 0xF03A, Instruction::PITCH_Vx),
+        (0xF0FF, 0xF055, Instruction::LD_I_Vx),
+        (0xF0FF, 0xF065, Instruction::LD_Vx_I),
+        (0xF0FF, 0xF075, Instruction::LD_R_Vx),
+        (0xF0FF, 0xF085, Instruction::LD_Vx_R),
+    ];
+
+    fn expected_instruction(opcode: u16) -> Instruction {
+        PATTERNS
+            .iter()
+            .find(|(mask, value, _)| opcode & mask == *value)
+            .map_or(Instruction::Invalid_Instruction, |(_, _, instruction)| *instruction)
+    }
+
+    #[test]
+    fn every_opcode_round_trips_or_is_invalid() {
+        let mut valid = 0;
+        for opcode in 0..=0xFFFFu16 {
+            let decoded = decode(opcode);
+            assert_eq!(decoded.instruction, expected_instruction(opcode), "{:#06x}", opcode);
+            match decoded.instruction {
+                Instruction::Invalid_Instruction => assert_eq!(encode(&decoded), None),
+                _ => {
+                    assert_eq!(encode(&decoded), Some(opcode), "{:#06x}", opcode);
+                    valid += 1;
+                }
+            }
+        }
+        // Unused encodings: 5xyN (13 N), 8xyN (7 N), 9xyN (15 N), ExNN (254 NN) and
+        // FxNN (242 NN, less F000 and F002)
+        let invalid = 13 * 0x100 + 7 * 0x100 + 15 * 0x100 + 254 * 0x10 + (242 * 0x10 - 2);
+        assert_eq!(valid, 0x10000 - invalid);
+    }
+
+    #[test]
+    fn lookup_matches_parse_opcode() {
+        for opcode in 0..=0xFFFFu16 {
+            assert_eq!(Instruction::lookup(opcode), Instruction::parse_opcode(opcode), "{:#06x}", opcode);
+        }
+    }
+
+    #[test]
+    fn strict_decoding() {
+        assert_eq!(Instruction::parse_opcode(0x00E0), Instruction::CLS);
+        assert_eq!(Instruction::parse_opcode(0x00E1), Instruction::SYS_addr);
+        assert_eq!(Instruction::parse_opcode(0x5121), Instruction::Invalid_Instruction);
+        assert_eq!(Instruction::parse_opcode(0x9121), Instruction::Invalid_Instruction);
+        assert_eq!(Instruction::parse_opcode(0x812F), Instruction::Invalid_Instruction);
+        assert_eq!(Instruction::parse_opcode(0xE19F), Instruction::Invalid_Instruction);
+        assert_eq!(Instruction::parse_opcode(0xF102), Instruction::Invalid_Instruction);
+    }
 }
@@ -1,4 +1,6 @@
-#[derive(Copy, Clone, Debug)]
+use crate::save_state::{SaveStateError, StateReader, StateWriter};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Key {
     Up,
     Down,
@@ -36,6 +38,22 @@ impl Keyboard {
         None
     }
 
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        for i in 0..16 {
+            w.bool(self.is_key_down(i));
+        }
+    }
+
+    pub(crate) fn read_state(r: &mut StateReader) -> Result<Keyboard, SaveStateError> {
+        let mut keyboard = Keyboard::new();
+        for key in keyboard.keys.iter_mut() {
+            if r.bool()? {
+                *key = Key::Down;
+            }
+        }
+        Ok(keyboard)
+    }
+
     pub fn set_key(&mut self, index: u8, state: Key) {
         if index > 15 {
             panic!("Key index greater than 15, only 16 keys exist");
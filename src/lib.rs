@@ -1,21 +1,34 @@
+use blend::{FrameBlend, DEFAULT_BLEND_FRAMES};
 use emulator::Emulator;
 use keyboard::{Key, Keyboard};
-use screen::Screen;
-use pixels::{Error, Pixels, SurfaceTexture};
+use palette::Palette;
+use phosphor::PhosphorFilter;
+use quirks::Quirks;
+use recompiler::Backend;
+use rewind::{RewindBuffer, DEFAULT_REWIND_FRAMES};
+use screen::{Resolution, Screen};
+use pixels::{Pixels, SurfaceTexture};
 use std::{
     env, 
     fs, 
+    path::Path,
     process::exit,
+    time::Duration,
     io::{stdout, stdin, Write},
 };
 use winit::{
-    dpi::LogicalSize,
+    dpi::{LogicalSize, PhysicalSize},
     event::*,
     event_loop::{ControlFlow, EventLoop},
     keyboard::{KeyCode, PhysicalKey},
-    window::{WindowBuilder, Window},
+    window::WindowBuilder,
 };
-use chrono::{Local, DateTime};
+#[cfg(not(target_arch = "wasm32"))]
+use chrono::Local;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 #[cfg(target_arch = "wasm32")]
 use web_sys::console;
 #[cfg(target_arch = "wasm32")]
@@ -26,23 +39,38 @@ use wasm_bindgen::JsValue;
 #[cfg(target_arch = "wasm32")]
 use winit::platform::web::EventLoopExtWebSys;
 
+pub mod asm;
+pub mod blend;
 pub mod emulator;
+pub mod error;
+pub mod headless;
 pub mod instruction;
 pub mod keyboard;
+pub mod palette;
+pub mod phosphor;
+pub mod quirks;
+pub mod recompiler;
+pub mod rewind;
+pub mod rng;
+pub mod save_state;
 pub mod screen;
+pub mod screenshot;
 pub mod wgpu_state;
 
 
 
-fn draw_pixels(pixels_buffer: &mut [u8], screen_buffer: &Vec<[u8; 4]>) {
-    for (pixel, cell) in pixels_buffer.chunks_exact_mut(4).zip(screen_buffer.iter()) {
-        pixel.copy_from_slice(cell);
-    }
-}
+// Roms start on the 64x32 screen, shown 8 times larger. The screen can change resolution while a
+// rom runs, the window then changes height to keep the pixels square.
+const START_RESOLUTION: Resolution = Resolution::Low;
+const WINDOW_SCALE: f64 = 8.0;
+
+// The emulator runs in 60Hz frames, the timer rate of the original hardware.
+const FRAME_MICROSECONDS: i64 = 16_667;
+const MAX_CATCH_UP_FRAMES: i64 = 4;
 
-// TODO implement other screen sizes for different chip8 instruction sets
-const WIDTH: u32 = 64;
-const HEIGHT: u32 = 32;
+// F5 saves the running rom to this file and F7 loads it back.
+#[cfg(not(target_arch = "wasm32"))]
+const SAVE_STATE_FILE: &str = "chip8.state";
 
 #[cfg(target_arch = "wasm32")]
 const BREAKOUT_ROM: &[u8] = include_bytes!("../Breakout.ch8");
@@ -86,6 +114,222 @@ fn read_file(e: &mut Emulator, file_path: Option<&String>) -> Result<(), ()>{
 }
 
 
+// Command line options for the emulator:
+//     chip8_emulator [--quirks <default|vip|vip-hires|chip48|schip|xochip>] [--seed <n>] [--ipf <n>] [--rewind <seconds>] [--recompiler]
+//         [--palette <default|amber|green|octo|file>] [--phosphor <decay>] [--blend <frames>] <rom>
+// --phosphor fades pixels out over several frames to hide flicker, decay is between 0 and 1,
+// the fraction of the afterglow lost each frame. --blend starts with frame blending on, showing
+// every pixel that was on in any of the last frames, F9 turns it on and off while running.
+// F12 saves a PNG screenshot, --scale makes every screen pixel that many pixels large.
+// or, to run without a window and print the final screen:
+//     chip8_emulator --headless [--frames <n>] [--key <frame>:<key>:<down|up>]... [--output <file>]
+//         [--screenshot <file.png|file.ppm>] [--scale <n>] [options] <rom>
+#[cfg(not(target_arch = "wasm32"))]
+struct Options {
+    rom: String,
+    quirks: Quirks,
+    seed: Option<u64>,
+    instructions_per_frame: Option<u32>,
+    rewind_frames: usize,
+    recompiler: bool,
+    palette: Palette,
+    phosphor: Option<f32>,
+    blend_frames: Option<usize>,
+    headless: bool,
+    frames: u32,
+    inputs: Vec<headless::Input>,
+    output: Option<String>,
+    screenshot: Option<String>,
+    scale: u32,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const DEFAULT_HEADLESS_FRAMES: u32 = 600;
+
+// Parses a headless key event written as <frame>:<key>:<down|up>, the key is a hex digit 0-F.
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_input(value: &str) -> Result<headless::Input, String> {
+    let invalid = || format!("Invalid key event \"{}\", expected <frame>:<key>:<down|up>", value);
+    let parts: Vec<&str> = value.split(':').collect();
+    if parts.len() != 3 {
+        return Err(invalid());
+    }
+    let frame = parts[0].parse().map_err(|_| invalid())?;
+    let key = u8::from_str_radix(parts[1], 16).map_err(|_| invalid())?;
+    if key > 15 {
+        return Err(invalid());
+    }
+    let state = match parts[2] {
+        "down" => Key::Down,
+        "up" => Key::Up,
+        _ => return Err(invalid()),
+    };
+    Ok(headless::Input { frame, key, state })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom: Option<String> = None;
+    let mut quirks = Quirks::default();
+    let mut seed: Option<u64> = None;
+    let mut instructions_per_frame: Option<u32> = None;
+    let mut rewind_frames = DEFAULT_REWIND_FRAMES;
+    let mut recompiler = false;
+    let mut palette = Palette::default();
+    let mut phosphor: Option<f32> = None;
+    let mut blend_frames: Option<usize> = None;
+    let mut headless = false;
+    let mut frames = DEFAULT_HEADLESS_FRAMES;
+    let mut inputs: Vec<headless::Input> = Vec::new();
+    let mut output: Option<String> = None;
+    let mut screenshot: Option<String> = None;
+    let mut scale = 1;
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let profile = args.next().ok_or("--quirks needs a profile name")?;
+                quirks = profile.parse().map_err(|e| format!("{}", e))?;
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a number")?;
+                seed = Some(value.parse().map_err(|_| format!("Invalid seed \"{}\"", value))?);
+            }
+            "--ipf" => {
+                let value = args.next().ok_or("--ipf needs a number")?;
+                instructions_per_frame = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid instructions per frame \"{}\"", value))?,
+                );
+            }
+            "--rewind" => {
+                let value = args.next().ok_or("--rewind needs a number of seconds")?;
+                let seconds: usize = value
+                    .parse()
+                    .map_err(|_| format!("Invalid rewind length \"{}\"", value))?;
+                rewind_frames = seconds * 60;
+            }
+            "--recompiler" => recompiler = true,
+            "--palette" => {
+                let value = args.next().ok_or("--palette needs a palette name or file")?;
+                // a built in name first, otherwise a palette file
+                palette = match value.parse() {
+                    Ok(palette) => palette,
+                    Err(_) => Palette::load(Path::new(value)).map_err(|e| format!("{}", e))?,
+                };
+            }
+            "--phosphor" => {
+                let value = args.next().ok_or("--phosphor needs a decay between 0 and 1")?;
+                let decay: f32 = value
+                    .parse()
+                    .map_err(|_| format!("Invalid phosphor decay \"{}\"", value))?;
+                if !(0.0..=1.0).contains(&decay) {
+                    return Err(format!("Phosphor decay {} is not between 0 and 1", decay));
+                }
+                phosphor = Some(decay);
+            }
+            "--blend" => {
+                let value = args.next().ok_or("--blend needs a number of frames")?;
+                match value.parse() {
+                    Ok(frames) if frames > 0 => blend_frames = Some(frames),
+                    _ => return Err(format!("Invalid blend frame count \"{}\"", value)),
+                }
+            }
+            "--headless" => headless = true,
+            "--frames" => {
+                let value = args.next().ok_or("--frames needs a number")?;
+                frames = value.parse().map_err(|_| format!("Invalid frame count \"{}\"", value))?;
+            }
+            "--key" => {
+                let value = args.next().ok_or("--key needs <frame>:<key>:<down|up>")?;
+                inputs.push(parse_input(value)?);
+            }
+            "--output" => {
+                output = Some(args.next().ok_or("--output needs a filename")?.clone());
+            }
+            "--screenshot" => {
+                screenshot = Some(args.next().ok_or("--screenshot needs a filename")?.clone());
+            }
+            "--scale" => {
+                let value = args.next().ok_or("--scale needs a number")?;
+                match value.parse() {
+                    Ok(n) if n > 0 => scale = n,
+                    _ => return Err(format!("Invalid screenshot scale \"{}\"", value)),
+                }
+            }
+            _ if rom.is_none() => rom = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument \"{}\"", arg)),
+        }
+    }
+    match rom {
+        Some(rom) => Ok(Options {
+            rom,
+            quirks,
+            seed,
+            instructions_per_frame,
+            rewind_frames,
+            recompiler,
+            palette,
+            phosphor,
+            blend_frames,
+            headless,
+            frames,
+            inputs,
+            output,
+            screenshot,
+            scale,
+        }),
+        None => Err(String::from("Needs a filename")),
+    }
+}
+
+
+// Run the rom without opening a window, then print the final screen or write it to the output file.
+// Returns the process exit code, non zero if the rom could not be run or stopped on an error.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_headless(e: Emulator, options: &Options) -> i32 {
+    let rom = match fs::read(&options.rom) {
+        Ok(rom) => rom,
+        Err(_) => {
+            println!("Rom file not found.");
+            return 1;
+        }
+    };
+    let phosphor = options.phosphor.map(PhosphorFilter::new);
+    let state = match headless::run_filtered(e, &rom, options.frames, &options.inputs, options.palette, phosphor) {
+        Ok(state) => state,
+        Err(message) => {
+            println!("{}", message);
+            return 1;
+        }
+    };
+    let text = state.screen_text();
+    match &options.output {
+        Some(output) => {
+            if let Err(err) = fs::write(output, &text) {
+                println!("Could not write {}: {}", output, err);
+                return 1;
+            }
+        }
+        None => print!("{}", text),
+    }
+    if let Some(screenshot) = &options.screenshot {
+        if let Err(err) = state.save_screenshot(Path::new(screenshot), options.scale) {
+            println!("{}", err);
+            return 1;
+        }
+    }
+    match state.error {
+        Some(err) => {
+            println!("EMULATOR ERROR: {}", err);
+            2
+        }
+        None => 0,
+    }
+}
+
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 pub fn init_loggers() {
     cfg_if::cfg_if! {
@@ -105,22 +349,54 @@ pub async fn run(mut e: Emulator) {
     
     // let mut e = Emulator::new();
     let mut k = Keyboard::new();
-    let mut s = Screen::new(WIDTH, HEIGHT);
+    let mut s = Screen::new(START_RESOLUTION.width(), START_RESOLUTION.height());
     
     
     #[cfg(target_arch = "wasm32")] {
         e.load_rom(BREAKOUT_ROM.to_vec());
     }
+    let mut rewind = RewindBuffer::new(DEFAULT_REWIND_FRAMES);
+    let mut phosphor: Option<PhosphorFilter> = None;
+    // Frame blending, F9 turns it on and off.
+    let mut blend = FrameBlend::new(DEFAULT_BLEND_FRAMES);
+    let mut blending = false;
+    // F12 screenshots are this many times the screen's size.
+    #[cfg(not(target_arch = "wasm32"))]
+    let screenshot_scale: u32;
     #[cfg(not(target_arch = "wasm32"))]
     { 
         let args: Vec<String> = env::args().collect();
-        let mut filename: String = String::new();
-        if args.len() != 2 {
-            println!("Needs a filename");
-            exit(-1);
-        } else {
-            filename = String::from(&args[1]);
-            let _ = read_file(&mut e, Some(&filename));
+        match parse_args(&args) {
+            Ok(options) => {
+                e.set_quirks(options.quirks);
+                if let Some(seed) = options.seed {
+                    e.set_seed(seed);
+                }
+                if let Some(instructions_per_frame) = options.instructions_per_frame {
+                    e.set_instructions_per_frame(instructions_per_frame);
+                }
+                rewind.set_capacity(options.rewind_frames);
+                if options.recompiler {
+                    e.set_backend(Backend::Recompiler);
+                }
+                s.set_palette(options.palette);
+                if let Some(decay) = options.phosphor {
+                    phosphor = Some(PhosphorFilter::new(decay));
+                }
+                if let Some(frames) = options.blend_frames {
+                    blend = FrameBlend::new(frames);
+                    blending = true;
+                }
+                screenshot_scale = options.scale;
+                if options.headless {
+                    exit(run_headless(e, &options));
+                }
+                let _ = read_file(&mut e, Some(&options.rom));
+            }
+            Err(message) => {
+                println!("{}", message);
+                exit(-1);
+            }
         }
     }
     
@@ -128,8 +404,9 @@ pub async fn run(mut e: Emulator) {
     let event_loop = EventLoop::new().unwrap();
     
     let window = {
-        let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
-        let scaled_size = LogicalSize::new(WIDTH as f64 * 8.0, HEIGHT as f64 * 8.0);
+        let (width, height) = (START_RESOLUTION.width() as f64, START_RESOLUTION.height() as f64);
+        let size = LogicalSize::new(width, height);
+        let scaled_size = LogicalSize::new(width * WINDOW_SCALE, height * WINDOW_SCALE);
         WindowBuilder::new()
         .with_title("Chip8")
         .with_inner_size(scaled_size)
@@ -142,7 +419,6 @@ pub async fn run(mut e: Emulator) {
     {
     // Winit prevents sizing with CSS, so we have to set
     // the size manually when on web.
-    use winit::dpi::PhysicalSize;
     let _ = window.request_inner_size(PhysicalSize::new(450, 450));
     
     use winit::platform::web::WindowExtWebSys;
@@ -160,21 +436,68 @@ pub async fn run(mut e: Emulator) {
     
     let mut pixels = {
         let window_size = window.inner_size();
-        let surface_texture = SurfaceTexture::new(WIDTH as u32 * 8, HEIGHT as u32 * 8, &window);
-        match Pixels::new_async(WIDTH as u32, HEIGHT as u32, surface_texture).await {
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        match Pixels::new_async(s.width(), s.height(), surface_texture).await {
             Ok(p) =>  p,
             Err(_) => panic!("failed to create Pixels object"),
         }
     };
 
-    let mut now = Local::now().time();
+    let mut now = Instant::now();
+    // Size of the pixels buffer, rebuilt when the emulator changes screen resolution
+    let mut buffer_size = (s.width(), s.height());
+    // Backspace is held down, run the game backwards through the rewind buffer.
+    let mut rewinding = false;
+    // The last redraw showed a filter's image, so the frame holds no rows of the plain screen.
+    let mut showed_filter = false;
+    // The browser has no file system to save into, so the web build keeps its save state in memory.
+    #[cfg(target_arch = "wasm32")]
+    let mut saved_state: Option<Vec<u8>> = None;
     
 
     let _ = event_loop.run(move |event, control_flow| {
-        let diff = (Local::now().time() - now).num_microseconds().unwrap_or(0);
-        if diff > 1851 {
-            e.emulate_step(&k, &mut s, diff);
-            now = Local::now().time();
+        // Run one emulator frame for every 60th of a second that has passed. If the frontend
+        // falls far behind, only catch up a few frames rather than fast forwarding.
+        let diff = now.elapsed().as_micros() as i64;
+        if diff >= FRAME_MICROSECONDS {
+            let frames = (diff / FRAME_MICROSECONDS).min(MAX_CATCH_UP_FRAMES);
+            let mut filter_changed = false;
+            for _ in 0..frames {
+                if rewinding {
+                    // Keys held now should not be replaced by the keys held in the past.
+                    let mut past_keys = Keyboard::new();
+                    if let Some(state) = rewind.pop() {
+                        let _ = e.load_state(&state, &mut past_keys, &mut s);
+                    }
+                } else if !e.is_paused() && !e.has_exited() {
+                    // Stop running the rom and show what went wrong, a new rom can still be loaded.
+                    match e.run_frame(&k, &mut s) {
+                        Ok(_) => rewind.push(e.save_state(&k, &s)),
+                        Err(err) => {
+                            println!("EMULATOR ERROR: {}", err);
+                            window.set_title(&format!("Chip8 - {}", err));
+                            e.pause();
+                        }
+                    }
+                }
+                // The filters move on once every emulated frame, so frames run to catch up are
+                // blended and faded like any other. Unchanged frames go into the blend too, so
+                // older frames drop out of it.
+                if blending {
+                    filter_changed |= blend.update(&s);
+                    let _ = s.take_dirty_rows();
+                } else if let Some(filter) = phosphor.as_mut() {
+                    if s.is_dirty() || filter.is_fading() {
+                        filter.update(&s);
+                        let _ = s.take_dirty_rows();
+                        filter_changed = true;
+                    }
+                }
+            }
+            now = Instant::now();
+            if filter_changed {
+                window.request_redraw();
+            }
         }
         match event {
             Event::WindowEvent { 
@@ -192,7 +515,36 @@ pub async fn run(mut e: Emulator) {
                         if window.inner_size().width <= 0 {
                             return;
                         }
-                        draw_pixels(pixels.frame_mut(), &s.screen_to_render());
+                        if (s.width(), s.height()) != buffer_size {
+                            buffer_size = (s.width(), s.height());
+                            if let Err(err) = pixels.resize_buffer(buffer_size.0, buffer_size.1) {
+                                println!("PIXEL BUFFER RESIZE ERROR: {}", err);
+                                control_flow.exit();
+                                return;
+                            }
+                            // Keep the window's width and fit its height to the new aspect ratio.
+                            let width = window.inner_size().width;
+                            let _ = window.request_inner_size(PhysicalSize::new(width, width * buffer_size.1 / buffer_size.0));
+                            window.set_min_inner_size(Some(LogicalSize::new(buffer_size.0 as f64, buffer_size.1 as f64)));
+                        }
+                        if blending {
+                            blend.render_into(pixels.frame_mut());
+                            showed_filter = true;
+                        } else if let Some(filter) = &phosphor {
+                            filter.render_into(pixels.frame_mut());
+                            showed_filter = true;
+                        } else {
+                            // The filters took the dirty rows while they were on, so once they are
+                            // turned off the whole screen has to be written again.
+                            if showed_filter {
+                                s.mark_all_dirty();
+                                showed_filter = false;
+                            }
+                            // Only the rows that changed since the last draw are written into the frame,
+                            // pixels.render still uploads the whole frame to the texture.
+                            let rows = s.take_dirty_rows();
+                            s.render_rows_into(pixels.frame_mut(), rows);
+                        }
                         if let Err(err) = pixels.render() {
                             println!("PIXEL DRAW ERROR: {}", err);
                             control_flow.exit();
@@ -208,6 +560,7 @@ pub async fn run(mut e: Emulator) {
                             control_flow.exit();
                             return;
                         }
+                        window.request_redraw();
                     },
                     // change rom
                     WindowEvent::KeyboardInput { 
@@ -217,14 +570,92 @@ pub async fn run(mut e: Emulator) {
                     } => {
                         #[cfg(not(target_arch = "wasm32"))]
                         {
-                            match read_file(&mut e, None) {
-                                Ok(_) => {
-                                    s.clear_screen();
-                                },
-                                Err(_) => (),
+                            if read_file(&mut e, None).is_ok() {
+                                s.reset(START_RESOLUTION);
+                                window.set_title("Chip8");
+                                e.unpause();
+                                rewind.clear();
                             }
-                            now = Local::now().time();
+                            now = Instant::now();
+                        }
+                    },
+                    // save states
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent { state: ElementState::Pressed, physical_key: PhysicalKey::Code(KeyCode::F5), ..},
+                            ..
+                    } => {
+                        let state = e.save_state(&k, &s);
+                        #[cfg(not(target_arch = "wasm32"))]
+                        match fs::write(SAVE_STATE_FILE, state) {
+                            Ok(_) => println!("Saved state to {}", SAVE_STATE_FILE),
+                            Err(err) => println!("Could not save state: {}", err),
+                        }
+                        #[cfg(target_arch = "wasm32")]
+                        {
+                            saved_state = Some(state);
+                        }
+                    },
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent { state: ElementState::Pressed, physical_key: PhysicalKey::Code(KeyCode::F7), ..},
+                            ..
+                    } => {
+                        #[cfg(not(target_arch = "wasm32"))]
+                        let state = fs::read(SAVE_STATE_FILE).ok();
+                        #[cfg(target_arch = "wasm32")]
+                        let state = saved_state.clone();
+                        match state {
+                            Some(state) => match e.load_state(&state, &mut k, &mut s) {
+                                Ok(_) => window.set_title("Chip8"),
+                                Err(err) => println!("Could not load state: {}", err),
+                            },
+                            None => println!("No saved state to load"),
                         }
+                        now = Instant::now();
+                    },
+                    // screenshots, saved in the current directory
+                    #[cfg(not(target_arch = "wasm32"))]
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent { state: ElementState::Pressed, physical_key: PhysicalKey::Code(KeyCode::F12), ..},
+                            ..
+                    } => {
+                        let path = Local::now().format("chip8-%Y%m%d-%H%M%S.png").to_string();
+                        match screenshot::save_screenshot(&s, Path::new(&path), screenshot_scale) {
+                            Ok(_) => println!("Saved screenshot to {}", path),
+                            Err(err) => println!("{}", err),
+                        }
+                    },
+                    // frame blending
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent { state: ElementState::Pressed, physical_key: PhysicalKey::Code(KeyCode::F9), ..},
+                            ..
+                    } => {
+                        blending = !blending;
+                        blend.reset();
+                        if let Some(filter) = phosphor.as_mut() {
+                            filter.reset();
+                        }
+                        // show the whole screen again the new way
+                        s.mark_all_dirty();
+                    },
+                    // rewind
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent { state, physical_key: PhysicalKey::Code(KeyCode::Backspace), ..},
+                            ..
+                    } => {
+                        let pressed = *state == ElementState::Pressed;
+                        // The newest state in the buffer is the frame on screen, drop it so the
+                        // first rewound frame already goes back in time. Held keys repeat, so
+                        // only do this when the key first goes down.
+                        if pressed && !rewinding {
+                            let _ = rewind.pop();
+                            window.set_title("Chip8");
+                        }
+                        rewinding = pressed;
                     },
                     // player input management
                     // TODO Add an option to change keybindings on start up. Can save in a config file
@@ -379,8 +810,104 @@ pub async fn run(mut e: Emulator) {
         //         k.set_key(15, Key::Up);
         //     }
         // }
-        // TODO instead of redrawing the frame every loop, can either send a signal from emulator when
-        // a drw instrctuion is run, or just check the instruction in this loop
-        window.request_redraw();
+        // Only redraw when the screen changed, then sleep until the next emulator frame is due.
+        // With a filter on, redraws are requested once a frame above.
+        if !blending && phosphor.is_none() && s.is_dirty() {
+            window.request_redraw();
+        }
+        let elapsed = now.elapsed().as_micros() as i64;
+        let wait = (FRAME_MICROSECONDS - elapsed).max(0) as u64;
+        control_flow.set_control_flow(ControlFlow::wait_duration(Duration::from_micros(wait)));
     });
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_rom_and_quirks() {
+        let options = parse_args(&args(&["chip8", "pong.ch8"])).unwrap();
+        assert_eq!(options.rom, "pong.ch8");
+        assert_eq!(options.quirks, Quirks::default());
+
+        let options = parse_args(&args(&["chip8", "--quirks", "schip", "pong.ch8"])).unwrap();
+        assert_eq!(options.rom, "pong.ch8");
+        assert_eq!(options.quirks, Quirks::schip());
+
+        let options = parse_args(&args(&["chip8", "--seed", "1234", "pong.ch8"])).unwrap();
+        assert_eq!(options.seed, Some(1234));
+
+        let options = parse_args(&args(&["chip8", "--ipf", "20", "pong.ch8"])).unwrap();
+        assert_eq!(options.instructions_per_frame, Some(20));
+        assert_eq!(options.rewind_frames, DEFAULT_REWIND_FRAMES);
+
+        let options = parse_args(&args(&["chip8", "--rewind", "30", "pong.ch8"])).unwrap();
+        assert_eq!(options.rewind_frames, 30 * 60);
+        assert!(!options.headless);
+        assert_eq!(options.palette, Palette::default());
+
+        let options = parse_args(&args(&["chip8", "--palette", "amber", "pong.ch8"])).unwrap();
+        assert_eq!(options.palette, Palette::amber());
+        assert!(parse_args(&args(&["chip8", "--palette", "no-such-palette.txt", "pong.ch8"])).is_err());
+        assert_eq!(options.phosphor, None);
+
+        let options = parse_args(&args(&["chip8", "--phosphor", "0.25", "pong.ch8"])).unwrap();
+        assert_eq!(options.phosphor, Some(0.25));
+        assert!(parse_args(&args(&["chip8", "--phosphor", "1.5", "pong.ch8"])).is_err());
+        assert!(parse_args(&args(&["chip8", "--phosphor", "slow", "pong.ch8"])).is_err());
+        assert_eq!(options.blend_frames, None);
+
+        let options = parse_args(&args(&["chip8", "--blend", "3", "pong.ch8"])).unwrap();
+        assert_eq!(options.blend_frames, Some(3));
+        assert!(parse_args(&args(&["chip8", "--blend", "0", "pong.ch8"])).is_err());
+    }
+
+    #[test]
+    fn parse_headless_args() {
+        let options = parse_args(&args(&[
+            "chip8", "--headless", "--frames", "120", "--key", "10:a:down", "--key", "20:A:up",
+            "--output", "screen.txt", "--recompiler", "pong.ch8",
+        ]))
+        .unwrap();
+        assert!(options.headless);
+        assert!(options.recompiler);
+        assert_eq!(options.frames, 120);
+        assert_eq!(
+            options.inputs,
+            vec![
+                headless::Input { frame: 10, key: 0xA, state: Key::Down },
+                headless::Input { frame: 20, key: 0xA, state: Key::Up },
+            ]
+        );
+        assert_eq!(options.output, Some(String::from("screen.txt")));
+        assert_eq!((options.screenshot, options.scale), (None, 1));
+        assert_eq!(options.rom, "pong.ch8");
+
+        assert!(parse_args(&args(&["chip8", "--headless", "--key", "10:g:down", "a.ch8"])).is_err());
+        assert!(parse_args(&args(&["chip8", "--headless", "--key", "10:1:held", "a.ch8"])).is_err());
+        assert!(parse_args(&args(&["chip8", "--headless", "--frames", "x", "a.ch8"])).is_err());
+
+        let options = parse_args(&args(&[
+            "chip8", "--headless", "--screenshot", "golden.png", "--scale", "4", "a.ch8",
+        ]))
+        .unwrap();
+        assert_eq!(options.screenshot, Some(String::from("golden.png")));
+        assert_eq!(options.scale, 4);
+        assert!(parse_args(&args(&["chip8", "--scale", "0", "a.ch8"])).is_err());
+    }
+
+    #[test]
+    fn parse_bad_args() {
+        assert!(parse_args(&args(&["chip8"])).is_err());
+        assert!(parse_args(&args(&["chip8", "a.ch8", "b.ch8"])).is_err());
+        assert!(parse_args(&args(&["chip8", "--quirks"])).is_err());
+        assert!(parse_args(&args(&["chip8", "--quirks", "megachip", "a.ch8"])).is_err());
+        assert!(parse_args(&args(&["chip8", "--seed", "abc", "a.ch8"])).is_err());
+        assert!(parse_args(&args(&["chip8", "--ipf", "-1", "a.ch8"])).is_err());
+    }
+}
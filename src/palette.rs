@@ -0,0 +1,180 @@
+use std::{error::Error, fmt, fs, path::Path, str::FromStr};
+
+// The four colours a screen pixel can be. XO-CHIP's two drawing planes pick the colour:
+//     0 neither plane, 1 only the first plane, 2 only the second plane, 3 both planes.
+// Monochrome roms only ever use colours 0 and 1.
+//
+// Palette files set any of the colours, one per line, as RRGGBB or RRGGBBAA hex with an
+// optional leading '#'. Colours that are not set keep the default palette's value:
+//     ; amber phosphor
+//     color0 = #000000
+//     color1 = #FFB000
+// ';' starts a comment, blank lines are ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub colors: [[u8; 4]; 4],
+}
+
+impl Default for Palette {
+    // White on a transparent background, with greys for the second plane.
+    fn default() -> Palette {
+        Palette {
+            colors: [
+                [0x00, 0x00, 0x00, 0x00],
+                [0xFF, 0xFF, 0xFF, 0xFF],
+                [0xAA, 0xAA, 0xAA, 0xFF],
+                [0x55, 0x55, 0x55, 0xFF],
+            ],
+        }
+    }
+}
+
+impl Palette {
+    // Amber phosphor monitor.
+    pub fn amber() -> Palette {
+        Palette {
+            colors: [
+                [0x00, 0x00, 0x00, 0xFF],
+                [0xFF, 0xB0, 0x00, 0xFF],
+                [0x99, 0x5C, 0x00, 0xFF],
+                [0xFF, 0xDD, 0x88, 0xFF],
+            ],
+        }
+    }
+
+    // Green phosphor monitor.
+    pub fn green() -> Palette {
+        Palette {
+            colors: [
+                [0x00, 0x00, 0x00, 0xFF],
+                [0x33, 0xFF, 0x33, 0xFF],
+                [0x11, 0x88, 0x11, 0xFF],
+                [0xAA, 0xFF, 0xAA, 0xFF],
+            ],
+        }
+    }
+
+    // Octo's default XO-CHIP colours.
+    pub fn octo() -> Palette {
+        Palette {
+            colors: [
+                [0x99, 0x66, 0x00, 0xFF],
+                [0xFF, 0xCC, 0x00, 0xFF],
+                [0xFF, 0x66, 0x00, 0xFF],
+                [0x66, 0x22, 0x00, 0xFF],
+            ],
+        }
+    }
+
+    // Parse the palette file format described above.
+    pub fn parse(text: &str) -> Result<Palette, PaletteError> {
+        let mut palette = Palette::default();
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| PaletteError { line: i + 1, message };
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("Expected colorN = RRGGBB, found \"{}\"", line)))?;
+            let index = match name.trim().to_lowercase().as_str() {
+                "color0" => 0,
+                "color1" => 1,
+                "color2" => 2,
+                "color3" => 3,
+                other => return Err(error(format!("Unknown colour \"{}\", expected color0-color3", other))),
+            };
+            palette.colors[index] = parse_color(value.trim())
+                .ok_or_else(|| error(format!("Invalid colour \"{}\", expected RRGGBB or RRGGBBAA", value.trim())))?;
+        }
+        Ok(palette)
+    }
+
+    pub fn load(path: &Path) -> Result<Palette, PaletteError> {
+        let text = fs::read_to_string(path).map_err(|err| PaletteError {
+            line: 0,
+            message: format!("Could not read {}: {}", path.display(), err),
+        })?;
+        Palette::parse(&text)
+    }
+}
+
+fn parse_color(value: &str) -> Option<[u8; 4]> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut color = [0xFF; 4];
+    for (i, channel) in color.iter_mut().enumerate().take(hex.len() / 2) {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(color)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteError {
+    // 0 if the file could not be read at all
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl Error for PaletteError {}
+
+impl FromStr for Palette {
+    type Err = PaletteError;
+
+    // A built in palette name, as accepted by the --palette command line option.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "default" | "white" => Ok(Palette::default()),
+            "amber" => Ok(Palette::amber()),
+            "green" => Ok(Palette::green()),
+            "octo" => Ok(Palette::octo()),
+            _ => Err(PaletteError {
+                line: 0,
+                message: format!(
+                    "Unknown palette \"{}\", expected one of: default, amber, green, octo, or a palette file",
+                    s
+                ),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_palette_file() {
+        let palette = Palette::parse("; amber\n\ncolor0 = #000000\nCOLOR1=FFB000 ; on\ncolor3 = 11223344\n")
+            .unwrap();
+        assert_eq!(palette.colors[0], [0, 0, 0, 0xFF]);
+        assert_eq!(palette.colors[1], [0xFF, 0xB0, 0, 0xFF]);
+        assert_eq!(palette.colors[2], Palette::default().colors[2]);
+        assert_eq!(palette.colors[3], [0x11, 0x22, 0x33, 0x44]);
+
+        assert_eq!(Palette::parse("color1 = #FFF").unwrap_err().line, 1);
+        assert_eq!(Palette::parse("\ncolor4 = #FFFFFF").unwrap_err().line, 2);
+        assert!(Palette::parse("color1 #FFFFFF").is_err());
+        assert!(Palette::parse("color1 = GGGGGG").is_err());
+    }
+
+    #[test]
+    fn named_palettes() {
+        assert_eq!("Amber".parse(), Ok(Palette::amber()));
+        assert_eq!("green".parse(), Ok(Palette::green()));
+        assert!("purple".parse::<Palette>().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::screen::Screen;
+
+// How much of the remaining afterglow fades away every frame when no decay is given.
+pub const DEFAULT_DECAY: f32 = 0.4;
+
+// Imitates the slow phosphor of a CRT to hide the flicker of roms that erase and redraw
+// sprites with XOR every frame. Pixels that get brighter light up at once, pixels that get
+// darker fade towards their new colour over several frames. It runs on the CPU between the
+// Screen and whatever shows the image, so headless runs can use it too.
+#[derive(Debug, Clone)]
+pub struct PhosphorFilter {
+    // The fraction of the difference to the screen's colour that fades each frame, 1 turns the
+    // filter off and values close to 0 leave long trails.
+    decay: f32,
+    width: u32,
+    height: u32,
+    // RGBA of every pixel as it is shown, fading towards the screen.
+    glow: Vec<[f32; 4]>,
+    // The screen rendered this frame, kept so updating does not allocate.
+    frame: Vec<u8>,
+    fading: bool,
+}
+
+impl PhosphorFilter {
+    pub fn new(decay: f32) -> PhosphorFilter {
+        PhosphorFilter {
+            decay: decay.clamp(0.0, 1.0),
+            width: 0,
+            height: 0,
+            glow: Vec::new(),
+            frame: Vec::new(),
+            fading: false,
+        }
+    }
+
+    pub fn decay(&self) -> f32 {
+        self.decay
+    }
+
+    pub fn set_decay(&mut self, decay: f32) {
+        self.decay = decay.clamp(0.0, 1.0);
+    }
+
+    // True while some pixel is still visibly fading, so the image changes without the screen changing.
+    pub fn is_fading(&self) -> bool {
+        self.fading
+    }
+
+    // Forget the afterglow, the next update shows the screen as it is.
+    pub fn reset(&mut self) {
+        self.width = 0;
+        self.height = 0;
+        self.glow.clear();
+        self.fading = false;
+    }
+
+    // Advance the filter by one 60Hz frame showing screen.
+    pub fn update(&mut self, screen: &Screen) {
+        self.frame.resize((screen.width() * screen.height() * 4) as usize, 0);
+        screen.render_into(&mut self.frame);
+        // a new resolution starts without any afterglow
+        if (screen.width(), screen.height()) != (self.width, self.height) {
+            self.width = screen.width();
+            self.height = screen.height();
+            self.glow = self
+                .frame
+                .chunks_exact(4)
+                .map(|pixel| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32, pixel[3] as f32])
+                .collect();
+            self.fading = false;
+            return;
+        }
+        let keep = 1.0 - self.decay;
+        let mut fading = false;
+        for (glow, pixel) in self.glow.iter_mut().zip(self.frame.chunks_exact(4)) {
+            for (channel, target) in glow.iter_mut().zip(pixel) {
+                let target = *target as f32;
+                *channel = if *channel > target {
+                    target + (*channel - target) * keep
+                } else {
+                    target
+                };
+                fading |= *channel - target >= 0.5;
+            }
+        }
+        self.fading = fading;
+    }
+
+    // Write the filtered image as RGBA into buffer, the same layout as Screen::render_into.
+    pub fn render_into(&self, buffer: &mut [u8]) {
+        for (pixel, glow) in buffer.chunks_exact_mut(4).zip(&self.glow) {
+            for (byte, channel) in pixel.iter_mut().zip(glow) {
+                *byte = channel.round() as u8;
+            }
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl Default for PhosphorFilter {
+    fn default() -> PhosphorFilter {
+        PhosphorFilter::new(DEFAULT_DECAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::Resolution;
+
+    fn rendered(filter: &PhosphorFilter) -> Vec<u8> {
+        let mut buffer = vec![0; (filter.width() * filter.height() * 4) as usize];
+        filter.render_into(&mut buffer);
+        buffer
+    }
+
+    #[test]
+    fn pixels_fade_out() {
+        let mut s = Screen::new(2, 1);
+        let mut filter = PhosphorFilter::new(0.5);
+        s.set_pixel(0, 0);
+        filter.update(&s);
+        assert_eq!(rendered(&filter), vec![0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+        assert!(!filter.is_fading());
+
+        // turned off, the pixel loses half its remaining brightness every frame
+        s.set_pixel(0, 0);
+        s.set_pixel(1, 0);
+        filter.update(&s);
+        assert_eq!(rendered(&filter), vec![0x80, 0x80, 0x80, 0x80, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert!(filter.is_fading());
+        filter.update(&s);
+        assert_eq!(rendered(&filter)[..4], [0x40; 4]);
+        for _ in 0..8 {
+            filter.update(&s);
+        }
+        assert_eq!(rendered(&filter)[..4], [0; 4]);
+        assert!(!filter.is_fading());
+    }
+
+    #[test]
+    fn flicker_is_smoothed() {
+        let mut s = Screen::new(1, 1);
+        let mut filter = PhosphorFilter::new(0.2);
+        for _ in 0..10 {
+            s.set_pixel(0, 0);
+            filter.update(&s);
+            assert!(rendered(&filter)[0] >= 0xCC);
+        }
+    }
+
+    #[test]
+    fn no_decay_and_new_resolution() {
+        let mut s = Screen::new(1, 1);
+        let mut filter = PhosphorFilter::new(1.0);
+        s.set_pixel(0, 0);
+        filter.update(&s);
+        s.set_pixel(0, 0);
+        filter.update(&s);
+        assert_eq!(rendered(&filter), vec![0; 4]);
+
+        filter.set_decay(0.1);
+        s.set_pixel(0, 0);
+        filter.update(&s);
+        s.set_resolution(Resolution::Low);
+        filter.update(&s);
+        assert_eq!((filter.width(), filter.height()), (64, 32));
+        assert!(rendered(&filter).iter().all(|byte| *byte == 0));
+        assert!(!filter.is_fading());
+    }
+}
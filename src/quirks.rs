@@ -0,0 +1,171 @@
+use std::{fmt, str::FromStr};
+
+use crate::screen::SpriteMode;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+// Chip8 was never formally specified, so a handful of instructions behave differently
+// depending on which interpreter a rom was written for. Each quirk picks one of the two
+// interpretations, false keeps the emulator's original behaviour.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quirks {
+    // SHR_Vx and SHL_Vx shift Vy and store the result in Vx, instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    // LD_I_Vx and LD_Vx_I leave I pointing at the address after the last register (I += x + 1).
+    pub load_store_increments_i: bool,
+    // JP_V0 (Bnnn) jumps to nnn + Vx, where x is the highest nibble of nnn, instead of nnn + V0.
+    pub jump_uses_vx: bool,
+    // OR_Vx_Vy, AND_Vx_Vy and XOR_Vx_Vy set VF to 0.
+    pub logic_resets_vf: bool,
+    // DRW wraps the starting coordinate but clips the rest of the sprite at the screen edges,
+    // instead of wrapping the whole sprite around to the other side.
+    pub clip_sprites: bool,
+    // Roms starting with JP 0x260 are VIP hi-res Chip8 roms: they run from 0x2C0 on a 64x64
+    // screen, and SYS 0x230 clears the screen.
+    pub vip_hires: bool,
+    // In hi-res mode DRW sets VF to the number of sprite rows that collided or were clipped
+    // off the bottom of the screen, instead of 1.
+    pub count_collided_rows: bool,
+    // SYS_addr stops the rom with an UnimplementedInstruction error instead of being ignored,
+    // since the machine code routine it calls cannot be run.
+    pub sys_unimplemented: bool,
+}
+
+impl Quirks {
+    // The original COSMAC VIP interpreter.
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            vip_hires: false,
+            count_collided_rows: false,
+            sys_unimplemented: false,
+        }
+    }
+
+    // The COSMAC VIP with the hi-res Chip8 interpreter.
+    pub fn vip_hires() -> Quirks {
+        Quirks {
+            vip_hires: true,
+            ..Quirks::cosmac_vip()
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            vip_hires: false,
+            count_collided_rows: false,
+            sys_unimplemented: false,
+        }
+    }
+
+    // SUPER-CHIP 1.1.
+    pub fn schip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            vip_hires: false,
+            count_collided_rows: true,
+            sys_unimplemented: false,
+        }
+    }
+
+    // XO-CHIP as implemented by Octo.
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            vip_hires: false,
+            count_collided_rows: false,
+            sys_unimplemented: false,
+        }
+    }
+
+    // The screen's sprite mode for DRW, picked by the clip_sprites quirk.
+    pub fn sprite_mode(&self) -> SpriteMode {
+        if self.clip_sprites {
+            SpriteMode::Clip
+        } else {
+            SpriteMode::Wrap
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UnknownQuirksProfile(String);
+
+impl fmt::Display for UnknownQuirksProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unknown quirks profile \"{}\", expected one of: default, vip, vip-hires, chip48, schip, xochip",
+            self.0
+        )
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = UnknownQuirksProfile;
+
+    // Parses the profile names accepted by the --quirks command line option.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "default" => Ok(Quirks::default()),
+            "vip" | "cosmac-vip" | "chip8" => Ok(Quirks::cosmac_vip()),
+            "vip-hires" | "hires" | "chip8-hires" => Ok(Quirks::vip_hires()),
+            "chip48" | "chip-48" => Ok(Quirks::chip48()),
+            "schip" | "superchip" | "super-chip" => Ok(Quirks::schip()),
+            "xochip" | "xo-chip" => Ok(Quirks::xo_chip()),
+            _ => Err(UnknownQuirksProfile(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_profiles() {
+        assert_eq!("default".parse(), Ok(Quirks::default()));
+        assert_eq!("VIP".parse(), Ok(Quirks::cosmac_vip()));
+        assert_eq!("vip-hires".parse(), Ok(Quirks::vip_hires()));
+        assert_eq!("chip48".parse(), Ok(Quirks::chip48()));
+        assert_eq!("schip".parse(), Ok(Quirks::schip()));
+        assert_eq!("xo-chip".parse(), Ok(Quirks::xo_chip()));
+        assert_eq!(
+            "megachip".parse::<Quirks>(),
+            Err(UnknownQuirksProfile(String::from("megachip")))
+        );
+    }
+
+    #[test]
+    fn default_is_original_behaviour() {
+        let q = Quirks::default();
+        assert!(!q.shift_uses_vy);
+        assert!(!q.load_store_increments_i);
+        assert!(!q.jump_uses_vx);
+        assert!(!q.logic_resets_vf);
+        assert!(!q.clip_sprites);
+        assert!(!q.vip_hires);
+        assert!(!q.count_collided_rows);
+        assert!(!q.sys_unimplemented);
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{
+    emulator::MEMORY_SIZE,
+    instruction::{decode, DecodedInstruction, Instruction},
+    quirks::Quirks,
+};
+use std::{ops::Range, sync::Arc};
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+// Block translator, the optional backend next to the interpreter in Emulator::emulate.
+// Straight-line code is translated once into a block of micro-ops. A block ends at the first
+// instruction that can move the pc anywhere but the next instruction (jumps, calls, returns,
+// skips, EXIT, waiting for a key), at draws, and at memory writes, so a rom patching its own
+// code never runs the stale rest of a block. Register arithmetic becomes micro-ops with the
+// quirks already applied, every other instruction runs the interpreter's handler. Writing to
+// memory a block was translated from throws the block away.
+
+// How run_frame executes instructions.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    // Fetch, decode and run one instruction at a time.
+    Interpreter,
+    // Run blocks translated by this module.
+    Recompiler,
+}
+
+// Longer runs of straight-line code are split into several blocks.
+const MAX_BLOCK_INSTRUCTIONS: usize = 64;
+
+const VF: usize = 0xF;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum MicroOp {
+    Load { x: usize, kk: u8 },
+    AddByte { x: usize, kk: u8 },
+    Copy { x: usize, y: usize },
+    Or { x: usize, y: usize, reset_vf: bool },
+    And { x: usize, y: usize, reset_vf: bool },
+    Xor { x: usize, y: usize, reset_vf: bool },
+    Add { x: usize, y: usize },
+    Sub { x: usize, y: usize },
+    SubN { x: usize, y: usize },
+    // source is Vx, or Vy with the shift_uses_vy quirk
+    ShiftRight { x: usize, source: usize },
+    ShiftLeft { x: usize, source: usize },
+    // Anything that touches more than the registers runs the interpreter's handler.
+    Interpret { opcode: u16, decoded: DecodedInstruction },
+}
+
+impl MicroOp {
+    pub(crate) fn translate(opcode: u16, quirks: &Quirks) -> MicroOp {
+        let decoded = decode(opcode);
+        let x = decoded.x.index();
+        let y = decoded.y.index();
+        let kk = decoded.kk.0;
+        let reset_vf = quirks.logic_resets_vf;
+        let source = if quirks.shift_uses_vy { y } else { x };
+        match decoded.instruction {
+            Instruction::LD_Vx => MicroOp::Load { x, kk },
+            Instruction::ADD_Vx => MicroOp::AddByte { x, kk },
+            Instruction::LD_Vx_Vy => MicroOp::Copy { x, y },
+            Instruction::OR_Vx_Vy => MicroOp::Or { x, y, reset_vf },
+            Instruction::AND_Vx_Vy => MicroOp::And { x, y, reset_vf },
+            Instruction::XOR_Vx_Vy => MicroOp::Xor { x, y, reset_vf },
+            Instruction::ADD_Vx_Vy => MicroOp::Add { x, y },
+            Instruction::SUB_Vx_Vy => MicroOp::Sub { x, y },
+            Instruction::SUBN_Vx_Vy => MicroOp::SubN { x, y },
+            Instruction::SHR_Vx => MicroOp::ShiftRight { x, source },
+            Instruction::SHL_Vx => MicroOp::ShiftLeft { x, source },
+            _ => MicroOp::Interpret { opcode, decoded },
+        }
+    }
+
+    // Run a register op. VF is written at the same point as in the interpreter's handlers, which
+    // matters when x or y is VF: add and subtract compute the result after the new flag is set.
+    pub(crate) fn run(self, registers: &mut [u8; 16]) {
+        match self {
+            MicroOp::Load { x, kk } => registers[x] = kk,
+            MicroOp::AddByte { x, kk } => registers[x] = registers[x].wrapping_add(kk),
+            MicroOp::Copy { x, y } => registers[x] = registers[y],
+            MicroOp::Or { x, y, reset_vf } => {
+                registers[x] |= registers[y];
+                if reset_vf {
+                    registers[VF] = 0;
+                }
+            }
+            MicroOp::And { x, y, reset_vf } => {
+                registers[x] &= registers[y];
+                if reset_vf {
+                    registers[VF] = 0;
+                }
+            }
+            MicroOp::Xor { x, y, reset_vf } => {
+                registers[x] ^= registers[y];
+                if reset_vf {
+                    registers[VF] = 0;
+                }
+            }
+            MicroOp::Add { x, y } => {
+                registers[VF] = registers[x].checked_add(registers[y]).is_none() as u8;
+                registers[x] = registers[x].wrapping_add(registers[y]);
+            }
+            MicroOp::Sub { x, y } => {
+                registers[VF] = (registers[x] > registers[y]) as u8;
+                registers[x] = registers[x].wrapping_sub(registers[y]);
+            }
+            MicroOp::SubN { x, y } => {
+                registers[VF] = (registers[y] > registers[x]) as u8;
+                registers[x] = registers[y].wrapping_sub(registers[x]);
+            }
+            MicroOp::ShiftRight { x, source } => {
+                let value = registers[source];
+                registers[x] = value >> 1;
+                registers[VF] = value & 0x01;
+            }
+            MicroOp::ShiftLeft { x, source } => {
+                let value = registers[source];
+                registers[x] = value << 1;
+                registers[VF] = value >> 7;
+            }
+            MicroOp::Interpret { .. } => unreachable!("interpreted ops are run by the emulator"),
+        }
+    }
+}
+
+pub(crate) struct Block {
+    start: usize,
+    // Bytes of memory the block was translated from.
+    len: usize,
+    // Each instruction's address and micro-op.
+    pub(crate) ops: Vec<(u16, MicroOp)>,
+    // Where the pc goes if the last op is a register op.
+    pub(crate) end: u16,
+}
+
+fn ends_block(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::JP_addr
+            | Instruction::JP_V0
+            | Instruction::CALL_addr
+            | Instruction::RET
+            | Instruction::EXIT
+            | Instruction::SE_Vx
+            | Instruction::SNE_Vx
+            | Instruction::SE_Vx_Vy
+            | Instruction::SNE_Vx_Vy
+            | Instruction::SKP_Vx
+            | Instruction::SKNP_Vx
+            | Instruction::LD_Vx_K
+            | Instruction::DRW_Vx_Vy
+            | Instruction::DRW_Vx_Vy_0
+            | Instruction::LD_B_Vx
+            | Instruction::LD_I_Vx
+            | Instruction::SAVE_Vx_Vy
+            | Instruction::Invalid_Instruction
+    )
+}
+
+// Translate the code starting at start. Stops before an opcode that would run past the end of
+// memory, so the block is empty if start is the last byte.
+pub(crate) fn translate_block(memory: &[u8; MEMORY_SIZE], start: u16, quirks: &Quirks) -> Block {
+    let mut ops = Vec::new();
+    let mut address = start as usize;
+    while address + 1 < MEMORY_SIZE && ops.len() < MAX_BLOCK_INSTRUCTIONS {
+        let opcode = ((memory[address] as u16) << 8) | (memory[address + 1] as u16);
+        let op = MicroOp::translate(opcode, quirks);
+        ops.push((address as u16, op));
+        let instruction = Instruction::lookup(opcode);
+        // the only 4 byte instruction, its address is stored in the next 2 bytes.
+        address += if instruction == Instruction::LD_I_long { 4 } else { 2 };
+        if ends_block(instruction) {
+            break;
+        }
+    }
+    Block {
+        start: start as usize,
+        len: address - start as usize,
+        ops,
+        end: address as u16,
+    }
+}
+
+// Translated blocks by start address. Empty until the first block is translated.
+#[derive(Default)]
+pub(crate) struct BlockCache {
+    blocks: Vec<Option<Arc<Block>>>,
+    // Every cached block was translated from memory in this range, most writes land outside it.
+    code: Range<usize>,
+}
+
+impl BlockCache {
+    pub(crate) fn get(&self, start: u16) -> Option<Arc<Block>> {
+        self.blocks.get(start as usize)?.clone()
+    }
+
+    pub(crate) fn insert(&mut self, block: Block) -> Arc<Block> {
+        if self.blocks.is_empty() {
+            self.blocks.resize(MEMORY_SIZE, None);
+        }
+        let block = Arc::new(block);
+        self.code = if self.code.is_empty() {
+            block.start..block.start + block.len
+        } else {
+            self.code.start.min(block.start)..self.code.end.max(block.start + block.len)
+        };
+        self.blocks[block.start] = Some(block.clone());
+        block
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.blocks.clear();
+        self.code = 0..0;
+    }
+
+    // Drop every block translated from any of the len bytes at start.
+    pub(crate) fn invalidate(&mut self, start: usize, len: usize) {
+        let end = start + len;
+        if end <= self.code.start || self.code.end <= start {
+            return;
+        }
+        // every instruction is at most 4 bytes, so blocks that reach start begin shortly before it
+        let first = start.saturating_sub(MAX_BLOCK_INSTRUCTIONS * 4);
+        for entry in &mut self.blocks[first..end.min(MEMORY_SIZE)] {
+            if entry.as_ref().is_some_and(|block| block.start + block.len > start) {
+                *entry = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        asm::assemble,
+        emulator::{Emulator, LOW_RES_HEIGHT, LOW_RES_WIDTH},
+        error::EmulatorError,
+        instruction::{Address, Byte, Nibble, Register},
+        keyboard::{Key, Keyboard},
+        screen::Screen,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn blocks_end_at_control_flow() {
+        let rom = assemble("LD V0, 1\nADD V0, V1\nLD I, LONG 0x1234\nDRW V0, V1, 5\nCLS").unwrap();
+        let mut memory = [0; MEMORY_SIZE];
+        memory[0x200..0x200 + rom.len()].copy_from_slice(&rom);
+        let block = translate_block(&memory, 0x200, &Quirks::default());
+        let addresses: Vec<u16> = block.ops.iter().map(|(address, _)| *address).collect();
+        assert_eq!(addresses, [0x200, 0x202, 0x204, 0x208]);
+        assert_eq!(block.ops[1].1, MicroOp::Add { x: 0, y: 1 });
+        assert_eq!(block.end, 0x20A);
+
+        let block = translate_block(&memory, 0xFFFE, &Quirks::default());
+        assert_eq!(block.ops.len(), 1);
+        assert!(translate_block(&memory, 0xFFFF, &Quirks::default()).ops.is_empty());
+    }
+
+    #[test]
+    fn invalidate_overlapping_blocks() {
+        let memory = [0x60; MEMORY_SIZE];
+        let mut cache = BlockCache::default();
+        cache.insert(translate_block(&memory, 0x200, &Quirks::default()));
+        cache.invalidate(0x200 + 2 * MAX_BLOCK_INSTRUCTIONS, 1);
+        assert!(cache.get(0x200).is_some());
+        cache.invalidate(0x200 + 2 * MAX_BLOCK_INSTRUCTIONS - 1, 1);
+        assert!(cache.get(0x200).is_none());
+    }
+
+    // Run rom on both backends frame by frame and check every state matches. Different frame
+    // lengths make the recompiler stop at different points inside its blocks.
+    fn assert_backends_agree(rom: &[u8], quirks: Quirks, instructions_per_frame: u32, frames: u32, seed: u64) {
+        let mut emulators = [Backend::Interpreter, Backend::Recompiler].map(|backend| {
+            let mut e = Emulator::with_seed(seed);
+            e.set_quirks(quirks);
+            e.set_instructions_per_frame(instructions_per_frame);
+            e.set_backend(backend);
+            e.load_rom(rom.to_vec()).unwrap();
+            (e, Screen::new(LOW_RES_WIDTH, LOW_RES_HEIGHT))
+        });
+        let mut keyboard = Keyboard::new();
+        for frame in 0..frames {
+            keyboard.set_key((frame % 16) as u8, if frame % 3 == 0 { Key::Down } else { Key::Up });
+            let results: Vec<Result<(), EmulatorError>> =
+                emulators.iter_mut().map(|(e, s)| e.run_frame(&keyboard, s)).collect();
+            assert_eq!(results[0], results[1], "frame {} of seed {}", frame, seed);
+            let [(interpreted, s0), (compiled, s1)] = &emulators;
+            assert!(
+                interpreted.save_state(&keyboard, s0) == compiled.save_state(&keyboard, s1),
+                "frame {} of seed {}",
+                frame,
+                seed
+            );
+            if results[0].is_err() || interpreted.has_exited() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn matches_interpreter_on_random_programs() {
+        let mut kinds: Vec<Instruction> = Vec::new();
+        for opcode in 0..=0xFFFFu16 {
+            let instruction = Instruction::lookup(opcode);
+            if instruction != Instruction::Invalid_Instruction && !kinds.contains(&instruction) {
+                kinds.push(instruction);
+            }
+        }
+        let quirks = [Quirks::default(), Quirks::cosmac_vip(), Quirks::schip(), Quirks::xo_chip()];
+        for seed in 0..200 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut rom = Vec::new();
+            while rom.len() < 256 {
+                // every instruction equally likely, with jumps and calls kept inside the program
+                let instruction = kinds[rng.gen_range(0..kinds.len())];
+                let opcode = instruction.to_opcode(
+                    Register(rng.gen_range(0..16)),
+                    Register(rng.gen_range(0..16)),
+                    Nibble(rng.gen_range(0..16)),
+                    Byte(rng.gen()),
+                    Address(0x200 + rng.gen_range(0..128) * 2),
+                );
+                rom.extend_from_slice(&opcode.unwrap().to_be_bytes());
+            }
+            let ipf = 1 + seed as u32 % 40;
+            assert_backends_agree(&rom, quirks[seed as usize % quirks.len()], ipf, 60, seed);
+        }
+    }
+
+    #[test]
+    fn matches_interpreter_on_self_modifying_code() {
+        // counts in V2, every 16th pass rewrites the ADD at `patch` with one from V0-V1
+        let rom = assemble(
+            "
+            LD V5, 0x0F
+    loop:
+            RND V1, 0x07
+            LD V0, 0x72
+    patch:
+            ADD V2, 1
+            LD V3, V2
+            AND V3, V5
+            SE V3, 0
+            JP loop
+            LD I, patch
+            LD [I], V1
+            LD I, digits
+            LD B, V2
+            JP loop
+    digits:
+            db 0, 0, 0
+            ",
+        )
+        .unwrap();
+        for seed in 0..8 {
+            assert_backends_agree(&rom, Quirks::default(), 1 + seed as u32 * 7, 120, seed);
+        }
+    }
+}
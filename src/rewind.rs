@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+
+// Number of frames kept by default, 10 seconds at 60 frames per second.
+pub const DEFAULT_REWIND_FRAMES: usize = 600;
+
+// How to turn a state back into the one saved before it.
+enum Delta {
+    // The states had different lengths (e.g. the screen resolution changed), keep the whole older state.
+    Full(Vec<u8>),
+    // (offset, older bytes) for every run of bytes that changed.
+    Runs(Vec<(usize, Vec<u8>)>),
+}
+
+impl Delta {
+    fn between(newer: &[u8], older: Vec<u8>) -> Delta {
+        if newer.len() != older.len() {
+            return Delta::Full(older);
+        }
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut i = 0;
+        while i < older.len() {
+            if older[i] == newer[i] {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < older.len() && older[i] != newer[i] {
+                i += 1;
+            }
+            runs.push((start, older[start..i].to_vec()));
+        }
+        Delta::Runs(runs)
+    }
+
+    fn apply(self, newer: &mut Vec<u8>) {
+        match self {
+            Delta::Full(older) => *newer = older,
+            Delta::Runs(runs) => {
+                for (offset, bytes) in runs {
+                    newer[offset..offset + bytes.len()].copy_from_slice(&bytes);
+                }
+            }
+        }
+    }
+}
+
+// Ring buffer of the most recent save states, one pushed per frame, so gameplay can be run backwards.
+// Only the newest state is stored in full, every older one is kept as the bytes that differ from the
+// state after it. Most frames only touch a few registers and screen pixels, so a frame costs a handful
+// of bytes rather than a whole copy of memory.
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+}
+
+impl RewindBuffer {
+    // capacity is the number of frames that can be rewound, at least 1.
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity: capacity.max(1),
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Change how many frames are kept, dropping the oldest ones if there are now too many.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.deltas.len() >= self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    // Add the newest state, dropping the oldest one once the buffer is full.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(older) = self.latest.take() {
+            self.deltas.push_back(Delta::between(&state, older));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // Remove and return the newest state, the one before it becomes the newest.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let state = self.latest.take()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            let mut older = state.clone();
+            delta.apply(&mut older);
+            self.latest = Some(older);
+        }
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pop_in_reverse_order() {
+        let mut r = RewindBuffer::new(10);
+        r.push(vec![1, 2, 3, 4]);
+        r.push(vec![1, 5, 6, 4]);
+        r.push(vec![1, 5, 6]);
+        r.push(vec![9, 5, 6]);
+        assert_eq!(r.len(), 4);
+        assert_eq!(r.pop(), Some(vec![9, 5, 6]));
+        assert_eq!(r.pop(), Some(vec![1, 5, 6]));
+        assert_eq!(r.pop(), Some(vec![1, 5, 6, 4]));
+        assert_eq!(r.pop(), Some(vec![1, 2, 3, 4]));
+        assert_eq!(r.pop(), None);
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn oldest_dropped_when_full() {
+        let mut r = RewindBuffer::new(3);
+        for i in 0..10u8 {
+            r.push(vec![i, 0, i]);
+        }
+        assert_eq!(r.len(), 3);
+        assert_eq!(r.pop(), Some(vec![9, 0, 9]));
+        assert_eq!(r.pop(), Some(vec![8, 0, 8]));
+        assert_eq!(r.pop(), Some(vec![7, 0, 7]));
+        assert_eq!(r.pop(), None);
+
+        for i in 0..10u8 {
+            r.push(vec![i]);
+        }
+        r.set_capacity(2);
+        assert_eq!(r.len(), 2);
+        assert_eq!(r.pop(), Some(vec![9]));
+        assert_eq!(r.pop(), Some(vec![8]));
+        assert_eq!(r.pop(), None);
+    }
+}
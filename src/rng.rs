@@ -0,0 +1,85 @@
+use rand::{Error, RngCore, SeedableRng};
+
+// Small seedable random number generator (SplitMix64) used by RND_Vx.
+// Its whole state is a single u64, so it can be stored in a save state and
+// a run can be replayed exactly from the same seed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chip8Rng {
+    state: u64,
+}
+
+impl Chip8Rng {
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    // Restore a generator from a value previously returned by state().
+    pub fn from_state(state: u64) -> Chip8Rng {
+        Chip8Rng { state }
+    }
+}
+
+impl RngCore for Chip8Rng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for Chip8Rng {
+    type Seed = [u8; 8];
+
+    fn from_seed(seed: Self::Seed) -> Chip8Rng {
+        Chip8Rng {
+            state: u64::from_le_bytes(seed),
+        }
+    }
+
+    fn seed_from_u64(seed: u64) -> Chip8Rng {
+        Chip8Rng { state: seed }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Chip8Rng::seed_from_u64(1234);
+        let mut b = Chip8Rng::seed_from_u64(1234);
+        let mut c = Chip8Rng::seed_from_u64(4321);
+        let a_values: Vec<u64> = (0..8).map(|_| a.next_u64()).collect();
+        let b_values: Vec<u64> = (0..8).map(|_| b.next_u64()).collect();
+        let c_values: Vec<u64> = (0..8).map(|_| c.next_u64()).collect();
+        assert_eq!(a_values, b_values);
+        assert_ne!(a_values, c_values);
+    }
+
+    #[test]
+    fn restore_from_state() {
+        let mut a = Chip8Rng::seed_from_u64(99);
+        a.next_u64();
+        let mut b = Chip8Rng::from_state(a.state());
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+}
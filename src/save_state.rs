@@ -0,0 +1,181 @@
+use std::{error::Error, fmt};
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::JsValue;
+
+// Save states are a small binary file:
+//     magic "C8ST", u16 format version, then the emulator, screen and keyboard sections.
+// All numbers are little endian. Bump SAVE_STATE_VERSION whenever the layout changes,
+// older files are then rejected instead of being loaded into the wrong fields.
+pub const SAVE_STATE_MAGIC: [u8; 4] = *b"C8ST";
+pub const SAVE_STATE_VERSION: u16 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SaveStateError {
+    // The data does not start with SAVE_STATE_MAGIC.
+    NotASaveState,
+    // The file was written by a different version of the format.
+    UnsupportedVersion(u16),
+    // The data ended before every field was read.
+    Truncated,
+    // A field holds a value the emulator can not be in, e.g. a screen size of 0.
+    InvalidData(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::NotASaveState => write!(f, "Not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported save state version {}, expected {}",
+                version, SAVE_STATE_VERSION
+            ),
+            SaveStateError::Truncated => write!(f, "Save state is truncated"),
+            SaveStateError::InvalidData(field) => write!(f, "Save state has an invalid {}", field),
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+#[cfg(target_arch = "wasm32")]
+impl From<SaveStateError> for JsValue {
+    fn from(error: SaveStateError) -> JsValue {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+pub(crate) struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn new() -> StateWriter {
+        let mut w = StateWriter { data: Vec::new() };
+        w.bytes(&SAVE_STATE_MAGIC);
+        w.u16(SAVE_STATE_VERSION);
+        w
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    // Checks the header, the returned reader starts at the first section.
+    pub(crate) fn new(data: &'a [u8]) -> Result<StateReader<'a>, SaveStateError> {
+        let mut r = StateReader { data };
+        match r.bytes(SAVE_STATE_MAGIC.len()) {
+            Ok(magic) if magic == SAVE_STATE_MAGIC => (),
+            _ => return Err(SaveStateError::NotASaveState),
+        }
+        let version = r.u16()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        Ok(r)
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidData("flag")),
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    // Trailing bytes mean the file does not match this version's layout.
+    pub(crate) fn finish(self) -> Result<(), SaveStateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(SaveStateError::InvalidData("length"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_checked() {
+        let mut w = StateWriter::new();
+        w.u32(0xDEADBEEF);
+        let data = w.finish();
+        let mut r = StateReader::new(&data).unwrap();
+        assert_eq!(r.u32(), Ok(0xDEADBEEF));
+        assert_eq!(r.u8(), Err(SaveStateError::Truncated));
+
+        assert_eq!(
+            StateReader::new(b"NOPE\x01\x00").err(),
+            Some(SaveStateError::NotASaveState)
+        );
+        assert_eq!(
+            StateReader::new(b"C8ST\x09\x00").err(),
+            Some(SaveStateError::UnsupportedVersion(9))
+        );
+        assert_eq!(StateReader::new(b"C8ST\x04").err(), Some(SaveStateError::Truncated));
+    }
+}
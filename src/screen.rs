@@ -1,18 +1,106 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum PixelSet {
     PixelOn,
     PixelOff,
 }
+use std::ops::Range;
+
+use crate::{
+    emulator::{HIGH_RES_HEIGHT, HIGH_RES_WIDTH, LOW_RES_HEIGHT, LOW_RES_WIDTH},
+    palette::Palette,
+    save_state::{SaveStateError, StateReader, StateWriter},
+};
+
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+// The display modes roms can switch between while running.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    // 64x32, the original Chip8 screen and Super Chip's LOW mode.
+    Low,
+    // 128x64, Super Chip's HIGH mode.
+    High,
+    // 64x64, the COSMAC VIP hi-res Chip8 variant.
+    VipHires,
+}
+
+impl Resolution {
+    pub fn width(self) -> u32 {
+        match self {
+            Resolution::Low | Resolution::VipHires => LOW_RES_WIDTH,
+            Resolution::High => HIGH_RES_WIDTH,
+        }
+    }
+
+    pub fn height(self) -> u32 {
+        match self {
+            Resolution::Low => LOW_RES_HEIGHT,
+            Resolution::High => HIGH_RES_HEIGHT,
+            Resolution::VipHires => 64,
+        }
+    }
+}
+
+// How sprites that run past the edge of the screen are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SpriteMode {
+    // Every pixel wraps around to the other side of the screen.
+    #[default]
+    Wrap,
+    // Only the starting coordinate wraps, the rest of the sprite is cut off at the right and
+    // bottom edges like on the original hardware.
+    Clip,
+}
+
+// Which rows of a sprite collided with pixels already on, bit i is row i.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SpriteReport {
+    // rows that turned off at least one pixel
+    pub collided_rows: u16,
+    // rows dropped below the bottom edge of the screen in Clip mode
+    pub clipped_rows: u16,
+}
+
+impl SpriteReport {
+    // true if any pixel was turned off, what VF normally holds
+    pub fn collided(&self) -> bool {
+        self.collided_rows != 0
+    }
+
+    // The number of rows that collided or were clipped, what Super Chip puts in VF in hi-res mode.
+    pub fn row_count(&self) -> u8 {
+        (self.collided_rows | self.clipped_rows).count_ones() as u8
+    }
+
+    // Combine the reports of drawing a sprite on several planes.
+    pub fn merge(self, other: SpriteReport) -> SpriteReport {
+        SpriteReport {
+            collided_rows: self.collided_rows | other.collided_rows,
+            clipped_rows: self.clipped_rows | other.clipped_rows,
+        }
+    }
+}
+
+// XO-CHIP draws on two bitplanes, Chip8 and Super Chip roms only use the first.
+pub const PLANE_COUNT: usize = 2;
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 #[derive(Debug)]
 pub struct Screen {
     width: u32,
     height: u32,
-    color: [u8; 4],
-    screen: Vec<PixelSet>, // pixels is a flat array, with 4 values for color
+    palette: Palette,
+    // Planes selected with XO-CHIP's FN01, bit 0 is the first plane. Drawing, clearing and
+    // scrolling only touch the selected planes.
+    plane_mask: u8,
+    sprite_mode: SpriteMode,
+    // Rows changed since the frontend last took them, empty when the screen has not changed.
+    dirty_rows: Range<u32>,
+    // One flat array of width * height pixels per plane. A pixel's colour is the palette entry
+    // made from its bits in every plane.
+    planes: [Vec<PixelSet>; PLANE_COUNT],
 }
 
 impl Screen {
@@ -20,39 +108,116 @@ impl Screen {
         Screen {
             width: width,
             height: height,
-            color: [0xFF, 0xFF, 0xFF, 0xFF],
-            screen: vec![PixelSet::PixelOff; (width * height) as usize],
+            palette: Palette::default(),
+            plane_mask: 1,
+            sprite_mode: SpriteMode::Wrap,
+            dirty_rows: 0..height,
+            planes: [(); PLANE_COUNT].map(|_| vec![PixelSet::PixelOff; (width * height) as usize]),
         }
     }
 
-    // returns true if pixel overlaps
-    pub fn set_pixel(&mut self, x: u32, y: u32) -> bool {
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.mark_all_dirty();
+    }
+
+    // True if anything changed on screen since the dirty rows were last taken.
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty_rows.is_empty()
+    }
+
+    // The rows changed since the last call, for the frontend to redraw, and mark the screen clean.
+    pub fn take_dirty_rows(&mut self) -> Range<u32> {
+        std::mem::replace(&mut self.dirty_rows, 0..0)
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty_rows = 0..self.height;
+    }
+
+    fn mark_row_dirty(&mut self, y: u32) {
+        if self.dirty_rows.is_empty() {
+            self.dirty_rows = y..y + 1;
+        } else {
+            self.dirty_rows = self.dirty_rows.start.min(y)..self.dirty_rows.end.max(y + 1);
+        }
+    }
+
+    pub fn plane_mask(&self) -> u8 {
+        self.plane_mask
+    }
+
+    // Select the planes later drawing, clearing and scrolling apply to, only the low 2 bits are used.
+    pub fn set_plane_mask(&mut self, mask: u8) {
+        self.plane_mask = mask & 0x3;
+    }
+
+    // Indices of the selected planes, first plane first.
+    pub fn selected_planes(&self) -> impl Iterator<Item = usize> {
+        let mask = self.plane_mask;
+        (0..PLANE_COUNT).filter(move |plane| mask & (1 << plane) != 0)
+    }
+
+    // Flip one pixel of plane, returns true if it was on before.
+    pub fn set_plane_pixel(&mut self, plane: usize, x: u32, y: u32) -> bool {
         if x >= self.width || y >= self.height {
             panic!("TRYING TO SET PIXEL OUT OF SCREEN RANGE");
         }
-        let pos = (y * self.width) + x;
-        match self.screen[pos as usize] {
+        let pos = ((y * self.width) + x) as usize;
+        self.mark_row_dirty(y);
+        match self.planes[plane][pos] {
             PixelSet::PixelOff => {
-                self.screen[pos as usize] = PixelSet::PixelOn;
+                self.planes[plane][pos] = PixelSet::PixelOn;
                 return false;
             }
             PixelSet::PixelOn => {
-                self.screen[pos as usize] = PixelSet::PixelOff;
+                self.planes[plane][pos] = PixelSet::PixelOff;
                 return true;
             }
         }
     }
 
-    // Take a byte and a starting (x, y) position and draw the pixels to the screen.
-    // If a sprite goes off screen, it is wrapped around to the other side.
+    pub fn sprite_mode(&self) -> SpriteMode {
+        self.sprite_mode
+    }
+
+    pub fn set_sprite_mode(&mut self, mode: SpriteMode) {
+        self.sprite_mode = mode;
+    }
+
+    // returns true if pixel overlaps on any selected plane
+    pub fn set_pixel(&mut self, x: u32, y: u32) -> bool {
+        let mut overlap = false;
+        for plane in self.selected_planes() {
+            overlap |= self.set_plane_pixel(plane, x, y);
+        }
+        overlap
+    }
+
+    // Take a byte and a starting (x, y) position and draw the pixels to one plane.
+    // If a sprite goes off screen, it is wrapped around to the other side, or dropped in Clip mode.
     // Returns true if pixel overlap.
-    pub fn set_byte_pixels(&mut self, byte: u8, x: u32, y: u32) -> bool {
+    pub fn set_plane_byte_pixels(&mut self, plane: usize, byte: u8, x: u32, y: u32) -> bool {
+        self.set_plane_word_pixels(plane, (byte as u16) << 8, x, y)
+    }
+
+    // Same as set_plane_byte_pixels for a 16 pixel wide row, the high bit is the leftmost pixel.
+    pub fn set_plane_word_pixels(&mut self, plane: usize, word: u16, x: u32, y: u32) -> bool {
         let mut overlap: bool = false;
-        for i in 0..8 {
+        for i in 0..16 {
             // if bit is 1, set pixel.
             // if overlap from setting pixel, set overlap to true
-            if ((byte << i) & 0x80) == 0x80 {
-                if self.set_pixel((x + i) % self.width, y % self.height) {
+            if ((word << i) & 0x8000) == 0x8000 {
+                let (pixel_x, pixel_y) = match self.sprite_mode {
+                    SpriteMode::Wrap => ((x + i) % self.width, y % self.height),
+                    SpriteMode::Clip if x + i < self.width && y < self.height => (x + i, y),
+                    SpriteMode::Clip => continue,
+                };
+                if self.set_plane_pixel(plane, pixel_x, pixel_y) {
                     overlap = true;
                 }
             }
@@ -60,21 +225,247 @@ impl Screen {
         overlap
     }
 
+    // Where a sprite drawn at (x, y) starts, the start always wraps onto the screen.
+    pub fn sprite_origin(&self, x: u32, y: u32) -> (u32, u32) {
+        (x % self.width, y % self.height)
+    }
+
+    // Draw a sprite one byte wide and rows.len() tall to one plane, with its top left corner at (x, y).
+    pub fn draw_sprite(&mut self, plane: usize, rows: &[u8], x: u32, y: u32) -> SpriteReport {
+        self.draw_rows(plane, rows.iter().map(|byte| (*byte as u16) << 8), x, y)
+    }
+
+    // Draw Super Chip's 16x16 sprite, two bytes per row, to one plane.
+    pub fn draw_large_sprite(&mut self, plane: usize, rows: &[u8], x: u32, y: u32) -> SpriteReport {
+        let words = rows
+            .chunks_exact(2)
+            .map(|row| u16::from_be_bytes([row[0], row[1]]));
+        self.draw_rows(plane, words, x, y)
+    }
+
+    // Sprites are at most 16 rows tall, one bit of the report each.
+    fn draw_rows(&mut self, plane: usize, rows: impl Iterator<Item = u16>, x: u32, y: u32) -> SpriteReport {
+        let (x, y) = self.sprite_origin(x, y);
+        let mut report = SpriteReport::default();
+        for (i, word) in rows.enumerate() {
+            let row_y = y + i as u32;
+            if self.sprite_mode == SpriteMode::Clip && row_y >= self.height {
+                report.clipped_rows |= 1 << i;
+            } else if self.set_plane_word_pixels(plane, word, x, row_y) {
+                report.collided_rows |= 1 << i;
+            }
+        }
+        report
+    }
+
+    // Same as set_plane_byte_pixels, drawing the byte on every selected plane.
+    pub fn set_byte_pixels(&mut self, byte: u8, x: u32, y: u32) -> bool {
+        self.set_word_pixels((byte as u16) << 8, x, y)
+    }
+
+    // Same as set_plane_word_pixels, drawing the row on every selected plane.
+    pub fn set_word_pixels(&mut self, word: u16, x: u32, y: u32) -> bool {
+        let mut overlap = false;
+        for plane in self.selected_planes() {
+            overlap |= self.set_plane_word_pixels(plane, word, x, y);
+        }
+        overlap
+    }
+
+    // True if the pixel is on in any plane.
+    pub fn is_pixel_on(&self, x: u32, y: u32) -> bool {
+        self.color_index(x, y) != 0
+    }
+
+    // The pixel's palette index, bit n is set if the pixel is on in plane n. 0 out of range.
+    pub fn color_index(&self, x: u32, y: u32) -> usize {
+        if x >= self.width || y >= self.height {
+            return 0;
+        }
+        self.color_index_at((y * self.width + x) as usize)
+    }
+
+    fn color_index_at(&self, pos: usize) -> usize {
+        let mut index = 0;
+        for (plane, pixels) in self.planes.iter().enumerate() {
+            if pixels[pos] == PixelSet::PixelOn {
+                index |= 1 << plane;
+            }
+        }
+        index
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // The mode matching the current size, None for screens created at any other size.
+    pub fn resolution(&self) -> Option<Resolution> {
+        [Resolution::Low, Resolution::High, Resolution::VipHires]
+            .into_iter()
+            .find(|r| (r.width(), r.height()) == (self.width, self.height))
+    }
+
+    // Switch the screen to a new resolution while a rom is running. Used by the Super Chip
+    // LOW/HIGH instructions and the VIP hi-res mode, every plane is cleared on a switch but
+    // the palette and plane mask are kept.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.width = resolution.width();
+        self.height = resolution.height();
+        let len = (self.width * self.height) as usize;
+        self.planes = [(); PLANE_COUNT].map(|_| vec![PixelSet::PixelOff; len]);
+        self.mark_all_dirty();
+    }
+
+    // Back to the state a new rom starts in: blank, at resolution, with only the first plane selected.
+    pub fn reset(&mut self, resolution: Resolution) {
+        self.set_resolution(resolution);
+        self.plane_mask = 1;
+    }
+
+    // Move every row of the selected planes down n pixels, rows scrolled in at the top are blank.
+    pub fn scroll_down(&mut self, n: u32) {
+        self.mark_all_dirty();
+        let n = n.min(self.height);
+        let shift = (n * self.width) as usize;
+        for plane in self.selected_planes() {
+            let pixels = &mut self.planes[plane];
+            let len = pixels.len();
+            pixels.copy_within(0..len - shift, shift);
+            pixels[..shift]
+                .iter_mut()
+                .for_each(|item| *item = PixelSet::PixelOff);
+        }
+    }
+
+    // Move every row of the selected planes up n pixels, rows scrolled in at the bottom are blank.
+    pub fn scroll_up(&mut self, n: u32) {
+        self.mark_all_dirty();
+        let n = n.min(self.height);
+        let shift = (n * self.width) as usize;
+        for plane in self.selected_planes() {
+            let pixels = &mut self.planes[plane];
+            let len = pixels.len();
+            pixels.copy_within(shift..len, 0);
+            pixels[len - shift..]
+                .iter_mut()
+                .for_each(|item| *item = PixelSet::PixelOff);
+        }
+    }
+
+    // Move every column of the selected planes 4 pixels to the right.
+    pub fn scroll_right(&mut self) {
+        self.mark_all_dirty();
+        let shift = 4.min(self.width) as usize;
+        for plane in self.selected_planes() {
+            for row in self.planes[plane].chunks_exact_mut(self.width as usize) {
+                row.rotate_right(shift);
+                row[..shift]
+                    .iter_mut()
+                    .for_each(|item| *item = PixelSet::PixelOff);
+            }
+        }
+    }
+
+    // Move every column of the selected planes 4 pixels to the left.
+    pub fn scroll_left(&mut self) {
+        self.mark_all_dirty();
+        let shift = 4.min(self.width) as usize;
+        for plane in self.selected_planes() {
+            for row in self.planes[plane].chunks_exact_mut(self.width as usize) {
+                row.rotate_left(shift);
+                let row_len = row.len();
+                row[row_len - shift..]
+                    .iter_mut()
+                    .for_each(|item| *item = PixelSet::PixelOff);
+            }
+        }
+    }
+
+    // Turn off every pixel of the selected planes.
     pub fn clear_screen(&mut self) {
-        self.screen
-            .iter_mut()
-            .for_each(|item| *item = PixelSet::PixelOff);
+        self.mark_all_dirty();
+        for plane in self.selected_planes() {
+            self.planes[plane]
+                .iter_mut()
+                .for_each(|item| *item = PixelSet::PixelOff);
+        }
     }
 
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.u32(self.width);
+        w.u32(self.height);
+        w.u8(self.plane_mask);
+        for plane in self.planes.iter() {
+            for pixel in plane.iter() {
+                w.bool(matches!(pixel, PixelSet::PixelOn));
+            }
+        }
+    }
+
+    // Read a screen written by write_state. The palette is a frontend setting, so it is kept from self.
+    pub(crate) fn read_state(&self, r: &mut StateReader) -> Result<Screen, SaveStateError> {
+        let width = r.u32()?;
+        let height = r.u32()?;
+        if width == 0 || height == 0 || width > 1024 || height > 1024 {
+            return Err(SaveStateError::InvalidData("screen size"));
+        }
+        let plane_mask = r.u8()?;
+        if plane_mask > 0x3 {
+            return Err(SaveStateError::InvalidData("plane mask"));
+        }
+        let mut planes: [Vec<PixelSet>; PLANE_COUNT] = Default::default();
+        for plane in planes.iter_mut() {
+            plane.reserve((width * height) as usize);
+            for _ in 0..width * height {
+                plane.push(if r.bool()? { PixelSet::PixelOn } else { PixelSet::PixelOff });
+            }
+        }
+        Ok(Screen {
+            width,
+            height,
+            palette: self.palette,
+            plane_mask,
+            // chosen by the emulator's quirks on every draw, so not part of the state
+            sprite_mode: self.sprite_mode,
+            dirty_rows: 0..height,
+            planes,
+        })
+    }
+
+    // Every pixel's colour from the palette, row by row.
     pub fn screen_to_render(&self) -> Vec<[u8; 4]> {
-        self.screen
-            .iter()
-            .map(|item| match item {
-                PixelSet::PixelOn => self.color,
-                PixelSet::PixelOff => [0, 0, 0, 0],
-            })
+        (0..(self.width * self.height) as usize)
+            .map(|pos| self.palette.colors[self.color_index_at(pos)])
             .collect()
     }
+
+    // Write every pixel's RGBA colour straight into buffer, row by row, without allocating.
+    // buffer needs at least width * height * 4 bytes, like the frame of a pixels surface.
+    pub fn render_into(&self, buffer: &mut [u8]) {
+        self.render_rows_into(buffer, 0..self.height);
+    }
+
+    // Same as render_into for only rows, the rest of buffer is left as it was.
+    pub fn render_rows_into(&self, buffer: &mut [u8], rows: Range<u32>) {
+        let width = self.width as usize;
+        let first = rows.start.min(self.height) as usize * width;
+        let end = rows.end.min(self.height) as usize * width;
+        // walk both planes side by side rather than looking every pixel up on its own
+        let [first_plane, second_plane] = &self.planes;
+        let pixels = buffer[first * 4..end * 4]
+            .chunks_exact_mut(4)
+            .zip(&first_plane[first..end])
+            .zip(&second_plane[first..end]);
+        for ((pixel, low), high) in pixels {
+            let index = (*low == PixelSet::PixelOn) as usize | ((*high == PixelSet::PixelOn) as usize) << 1;
+            pixel.copy_from_slice(&self.palette.colors[index]);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -87,18 +478,18 @@ mod tests {
 
         s.set_pixel(1, 1);
         let output = s.screen_to_render();
-        let comparison: Vec<[u8; 4]> = vec![[0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], s.color];
+        let comparison: Vec<[u8; 4]> = vec![[0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], s.palette.colors[1]];
         assert_eq!(output, comparison);
     }
 
     #[test]
     fn render_screen() {
         let mut s = Screen::new(8, 8);
-        let c = s.color;
+        let c = s.palette.colors[1];
         let z: [u8; 4] = [0, 0, 0, 0];
-        s.screen[0] = PixelSet::PixelOn;
-        s.screen[9] = PixelSet::PixelOn;
-        s.screen[10] = PixelSet::PixelOn;
+        s.planes[0][0] = PixelSet::PixelOn;
+        s.planes[0][9] = PixelSet::PixelOn;
+        s.planes[0][10] = PixelSet::PixelOn;
 
         let output = s.screen_to_render();
         let mut comparison: Vec<[u8; 4]> = vec![z; 8 * 8];
@@ -111,7 +502,7 @@ mod tests {
     #[test]
     fn test_clear_screen() {
         let mut s = Screen::new(2, 2);
-        let c = s.color;
+        let c = s.palette.colors[1];
         s.set_pixel(1, 1);
         s.set_pixel(0, 0);
         let output = s.screen_to_render();
@@ -126,7 +517,7 @@ mod tests {
     #[test]
     fn test_byte_set() {
         let mut s = Screen::new(8, 2);
-        let c = s.color;
+        let c = s.palette.colors[1];
         let z: [u8; 4] = [0, 0, 0, 0];
         let byte_to_set = 0xFF;
         s.set_byte_pixels(byte_to_set, 0, 0);
@@ -134,4 +525,237 @@ mod tests {
         let comparison: Vec<[u8; 4]> = vec![c, c, c, c, c, c, c, c, z, z, z, z, z, z, z, z];
         assert_eq!(output, comparison);
     }
+
+    #[test]
+    fn switch_resolution() {
+        let mut s = Screen::new(LOW_RES_WIDTH, LOW_RES_HEIGHT);
+        s.palette.colors[3] = [1, 2, 3, 4];
+        s.set_plane_mask(3);
+        assert_eq!(s.resolution(), Some(Resolution::Low));
+        s.set_pixel(3, 3);
+        s.set_resolution(Resolution::VipHires);
+        assert_eq!((s.width(), s.height()), (64, 64));
+        assert_eq!(s.resolution(), Some(Resolution::VipHires));
+        assert!(!s.is_pixel_on(3, 3));
+        s.set_pixel(63, 63);
+        assert_eq!(s.screen_to_render()[64 * 64 - 1], [1, 2, 3, 4]);
+        s.set_resolution(Resolution::High);
+        assert_eq!(s.screen_to_render().len(), 128 * 64);
+        assert_eq!(s.plane_mask(), 3);
+        s.reset(Resolution::Low);
+        assert_eq!((s.resolution(), s.plane_mask()), (Some(Resolution::Low), 1));
+        assert_eq!(Screen::new(2, 2).resolution(), None);
+    }
+
+    #[test]
+    fn planes_combine_into_colors() {
+        let mut s = Screen::new(4, 1);
+        s.set_palette(Palette::octo());
+        let colors = Palette::octo().colors;
+        s.set_pixel(0, 0);
+        s.set_plane_mask(2);
+        s.set_pixel(1, 0);
+        s.set_plane_mask(3);
+        assert!(!s.set_byte_pixels(0x20, 0, 0));
+        assert!(s.set_pixel(2, 0));
+        assert_eq!(s.screen_to_render(), vec![colors[1], colors[2], colors[0], colors[0]]);
+        s.set_pixel(3, 0);
+        assert_eq!(s.color_index(3, 0), 3);
+
+        // only the selected plane is cleared
+        s.set_plane_mask(1);
+        s.clear_screen();
+        assert_eq!(s.screen_to_render(), vec![colors[0], colors[2], colors[0], colors[2]]);
+    }
+
+    #[test]
+    fn wrap_sprites() {
+        let mut s = Screen::new(8, 4);
+        s.set_pixel(1, 0);
+        // the sprite runs off the right and bottom edges and comes back on the other side
+        let report = s.draw_sprite(0, &[0xF0, 0xF0], 6, 3);
+        assert_eq!(report, SpriteReport { collided_rows: 0b10, clipped_rows: 0 });
+        assert_eq!(plane_rows(&s, 0), vec!["#.....##", "........", "........", "##....##"]);
+        assert!(!s.draw_sprite(0, &[0x80], 10, 6).collided());
+        assert!(s.is_pixel_on(2, 2));
+    }
+
+    #[test]
+    fn clip_sprites() {
+        let mut s = Screen::new(8, 4);
+        s.set_sprite_mode(SpriteMode::Clip);
+        s.set_pixel(1, 0);
+        // pixels past the edges are dropped, and so cannot collide
+        let report = s.draw_sprite(0, &[0xF0, 0xF0], 6, 3);
+        assert_eq!(report, SpriteReport { collided_rows: 0, clipped_rows: 0b10 });
+        assert_eq!((report.collided(), report.row_count()), (false, 1));
+        assert_eq!(plane_rows(&s, 0), vec![".#......", "........", "........", "......##"]);
+        let report = s.draw_sprite(0, &[0xF0, 0xF0], 7, 3);
+        assert_eq!(report, SpriteReport { collided_rows: 0b01, clipped_rows: 0b10 });
+        assert_eq!((report.collided(), report.row_count()), (true, 2));
+        assert_eq!(plane_rows(&s, 0), vec![".#......", "........", "........", "......#."]);
+
+        // the start still wraps onto the screen
+        assert_eq!(s.draw_sprite(0, &[0xC0, 0xC0, 0xC0], 8, 5), SpriteReport::default());
+        assert_eq!(plane_rows(&s, 0), vec![".#......", "##......", "##......", "##....#."]);
+        assert!(s.draw_sprite(0, &[0x80], 65, 64).collided());
+        assert!(!s.is_pixel_on(1, 0));
+    }
+
+    #[test]
+    fn test_word_set() {
+        let mut s = Screen::new(16, 2);
+        assert!(!s.set_word_pixels(0x8001, 0, 0));
+        assert!(!s.set_word_pixels(0xF000, 14, 1));
+        assert_eq!(plane_rows(&s, 0), vec!["#..............#", "##............##"]);
+        assert!(s.set_word_pixels(0x0001, 0, 0));
+        s.set_sprite_mode(SpriteMode::Clip);
+        assert!(!s.set_word_pixels(0xFFFF, 8, 0));
+        assert_eq!(plane_rows(&s, 0), vec!["#.......########", "##............##"]);
+    }
+
+    #[test]
+    fn large_sprite_rows() {
+        let mut s = Screen::new(32, 16);
+        s.set_sprite_mode(SpriteMode::Clip);
+        let sprite: Vec<u8> = (0..16).flat_map(|_| [0xFF, 0xFF]).collect();
+        s.set_pixel(20, 10);
+        s.set_pixel(30, 10);
+        s.set_pixel(30, 11);
+        // rows 2 and 3 collide, the bottom 8 rows are clipped
+        let report = s.draw_large_sprite(0, &sprite, 16, 8);
+        assert_eq!(report, SpriteReport { collided_rows: 0b1100, clipped_rows: 0xFF00 });
+        assert_eq!(report.row_count(), 10);
+        assert!(s.is_pixel_on(31, 15) && !s.is_pixel_on(15, 15) && !s.is_pixel_on(20, 10));
+    }
+
+    #[test]
+    fn render_into_buffer() {
+        let mut s = Screen::new(8, 4);
+        s.set_palette(Palette::octo());
+        s.draw_sprite(0, &[0xA5, 0x5A], 2, 1);
+        s.set_plane_mask(2);
+        s.draw_sprite(1, &[0xFF, 0xFF], 0, 2);
+        let mut buffer = vec![0x11; 8 * 4 * 4];
+        s.render_into(&mut buffer);
+        let expected: Vec<u8> = s.screen_to_render().into_iter().flatten().collect();
+        assert_eq!(buffer, expected);
+
+        // only the given rows are written
+        let mut buffer = vec![0x11; 8 * 4 * 4];
+        s.render_rows_into(&mut buffer, 1..2);
+        assert!(buffer[..32].iter().all(|byte| *byte == 0x11));
+        assert_eq!(buffer[32..64], expected[32..64]);
+        assert!(buffer[64..].iter().all(|byte| *byte == 0x11));
+        s.render_rows_into(&mut buffer, 3..10);
+        assert_eq!(buffer[96..], expected[96..]);
+    }
+
+    #[test]
+    fn dirty_rows() {
+        let mut s = Screen::new(8, 8);
+        assert_eq!(s.take_dirty_rows(), 0..8);
+        assert!(!s.is_dirty());
+
+        s.set_pixel(1, 5);
+        s.draw_sprite(0, &[0x80, 0x80], 0, 2);
+        assert!(s.is_dirty());
+        assert_eq!(s.take_dirty_rows(), 2..6);
+        assert_eq!(s.take_dirty_rows(), 0..0);
+
+        // clipped rows and pixels that are not drawn leave the screen clean
+        s.set_sprite_mode(SpriteMode::Clip);
+        s.draw_sprite(0, &[0x00, 0x00, 0x80], 0, 6);
+        assert!(!s.is_dirty());
+
+        s.scroll_left();
+        assert_eq!(s.take_dirty_rows(), 0..8);
+        s.clear_screen();
+        assert_eq!(s.take_dirty_rows(), 0..8);
+        s.set_resolution(Resolution::High);
+        assert_eq!(s.take_dirty_rows(), 0..64);
+        s.set_palette(Palette::amber());
+        assert!(s.is_dirty());
+    }
+
+    // The pixels of plane as rows of '#' and '.', for comparing scrolled screens.
+    fn plane_rows(s: &Screen, plane: usize) -> Vec<String> {
+        s.planes[plane]
+            .chunks_exact(s.width() as usize)
+            .map(|row| row.iter().map(|p| if *p == PixelSet::PixelOn { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_scroll_down() {
+        let mut s = Screen::new(8, 4);
+        s.set_byte_pixels(0x81, 0, 0);
+        s.set_byte_pixels(0x18, 0, 1);
+        s.scroll_down(2);
+        assert_eq!(plane_rows(&s, 0), vec!["........", "........", "#......#", "...##..."]);
+        s.scroll_down(1);
+        assert_eq!(plane_rows(&s, 0), vec!["........", "........", "........", "#......#"]);
+        s.scroll_down(10);
+        assert!(!s.planes[0].contains(&PixelSet::PixelOn));
+    }
+
+    #[test]
+    fn test_scroll_up() {
+        let mut s = Screen::new(8, 4);
+        s.set_byte_pixels(0x81, 0, 2);
+        s.set_byte_pixels(0x18, 0, 3);
+        s.scroll_up(2);
+        assert_eq!(plane_rows(&s, 0), vec!["#......#", "...##...", "........", "........"]);
+        s.scroll_up(0);
+        assert_eq!(plane_rows(&s, 0), vec!["#......#", "...##...", "........", "........"]);
+        s.scroll_up(4);
+        assert!(!s.planes[0].contains(&PixelSet::PixelOn));
+    }
+
+    #[test]
+    fn test_scroll_left_right() {
+        let mut s = Screen::new(8, 2);
+        s.set_byte_pixels(0xC3, 0, 0);
+        s.set_byte_pixels(0x3C, 0, 1);
+        s.scroll_right();
+        assert_eq!(plane_rows(&s, 0), vec!["....##..", "......##"]);
+        s.scroll_left();
+        assert_eq!(plane_rows(&s, 0), vec!["##......", "..##...."]);
+        s.scroll_left();
+        assert!(!s.planes[0].contains(&PixelSet::PixelOn));
+    }
+
+    #[test]
+    fn scroll_uses_current_resolution() {
+        let mut s = Screen::new(LOW_RES_WIDTH, LOW_RES_HEIGHT);
+        s.set_resolution(Resolution::High);
+        s.set_pixel(123, 0);
+        s.scroll_right();
+        assert!(s.is_pixel_on(127, 0));
+        s.scroll_down(63);
+        assert!(s.is_pixel_on(127, 63));
+        s.scroll_up(63);
+        s.scroll_left();
+        assert!(s.is_pixel_on(123, 0));
+    }
+
+    #[test]
+    fn scroll_only_selected_planes() {
+        let mut s = Screen::new(8, 2);
+        s.set_plane_mask(3);
+        s.set_byte_pixels(0xF0, 0, 0);
+        s.set_plane_mask(2);
+        s.scroll_right();
+        s.scroll_down(1);
+        assert_eq!(plane_rows(&s, 0), vec!["####....", "........"]);
+        assert_eq!(plane_rows(&s, 1), vec!["........", "....####"]);
+        s.set_plane_mask(0);
+        s.scroll_up(1);
+        s.scroll_left();
+        assert_eq!(plane_rows(&s, 1), vec!["........", "....####"]);
+        s.set_plane_mask(3);
+        s.scroll_up(1);
+        assert_eq!(plane_rows(&s, 0), vec!["........", "........"]);
+        assert_eq!(plane_rows(&s, 1), vec!["....####", "........"]);
+    }
 }
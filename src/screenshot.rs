@@ -0,0 +1,318 @@
+use std::{error::Error, fmt, fs, io, path::Path};
+
+use crate::screen::Screen;
+
+// Screenshots of the screen in the active palette, for bug reports and golden tests. Encoding is
+// done on the CPU from the Screen, so it needs no window or GPU. PNG keeps the palette's alpha,
+// PPM only has RGB and drops it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    // The format for a file name's extension, .png or .ppm.
+    pub fn from_path(path: &Path) -> Result<ImageFormat, ScreenshotError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        match extension.as_deref() {
+            Some("png") => Ok(ImageFormat::Png),
+            Some("ppm") => Ok(ImageFormat::Ppm),
+            _ => Err(ScreenshotError::UnknownFormat(path.display().to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    UnknownFormat(String),
+    ZeroScale,
+    Io(io::Error),
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScreenshotError::UnknownFormat(path) => {
+                write!(f, "Can not tell the image format of \"{}\", expected a .png or .ppm file", path)
+            }
+            ScreenshotError::ZeroScale => write!(f, "Screenshot scale must be at least 1"),
+            ScreenshotError::Io(err) => write!(f, "Could not write screenshot: {}", err),
+        }
+    }
+}
+
+impl Error for ScreenshotError {}
+
+impl From<io::Error> for ScreenshotError {
+    fn from(err: io::Error) -> Self {
+        ScreenshotError::Io(err)
+    }
+}
+
+// Write screen to path, every pixel scale x scale pixels large, in the format of the path's extension.
+pub fn save_screenshot(screen: &Screen, path: &Path, scale: u32) -> Result<(), ScreenshotError> {
+    let mut rgba = vec![0; (screen.width() * screen.height() * 4) as usize];
+    screen.render_into(&mut rgba);
+    save_image(&rgba, screen.width(), screen.height(), path, scale)
+}
+
+// Same as save_screenshot for an RGBA image that is already rendered, e.g. through a display filter.
+pub fn save_image(rgba: &[u8], width: u32, height: u32, path: &Path, scale: u32) -> Result<(), ScreenshotError> {
+    let format = ImageFormat::from_path(path)?;
+    if scale == 0 {
+        return Err(ScreenshotError::ZeroScale);
+    }
+    let rgba = scale_image(rgba, width, height, scale);
+    let (width, height) = (width * scale, height * scale);
+    let bytes = match format {
+        ImageFormat::Png => encode_png(&rgba, width, height),
+        ImageFormat::Ppm => encode_ppm(&rgba, width, height),
+    };
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+// Blow an RGBA image up so every pixel becomes a scale x scale square.
+pub fn scale_image(rgba: &[u8], width: u32, height: u32, scale: u32) -> Vec<u8> {
+    let (width, height, scale) = (width as usize, height as usize, scale as usize);
+    let mut scaled = Vec::with_capacity(rgba.len() * scale * scale);
+    for row in rgba.chunks_exact(width * 4).take(height) {
+        let start = scaled.len();
+        for pixel in row.chunks_exact(4) {
+            for _ in 0..scale {
+                scaled.extend_from_slice(pixel);
+            }
+        }
+        for _ in 1..scale {
+            scaled.extend_from_within(start..start + width * 4 * scale);
+        }
+    }
+    scaled
+}
+
+// Binary PPM (P6), 8 bits per channel.
+pub fn encode_ppm(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for pixel in rgba.chunks_exact(4).take((width * height) as usize) {
+        ppm.extend_from_slice(&pixel[..3]);
+    }
+    ppm
+}
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+// The most a stored (uncompressed) deflate block can hold.
+const STORED_BLOCK_LEN: usize = 0xFFFF;
+
+// 8 bit RGBA PNG. The image data is not compressed, it goes into stored deflate blocks, which
+// keeps the encoder small and screenshots of a 128x64 screen are small anyway.
+pub fn encode_png(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut png = PNG_SIGNATURE.to_vec();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // bit depth 8, colour type 6 (RGBA), default compression and filter, no interlacing
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+
+    // every row starts with its filter type, 0 for none
+    let row_len = width as usize * 4;
+    let mut raw = Vec::with_capacity((row_len + 1) * height as usize);
+    for row in rgba.chunks_exact(row_len.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// Length, type, data and the CRC of type and data.
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream holding data in stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(STORED_BLOCK_LEN).max(1);
+    let mut zlib = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    // deflate with a 32K window, no preset dictionary, header check bits make it a multiple of 31
+    zlib.extend_from_slice(&[0x78, 0x01]);
+    let mut chunks = data.chunks(STORED_BLOCK_LEN).peekable();
+    if chunks.peek().is_none() {
+        zlib.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        // the first bit marks the last block, the next two bits are 00 for stored
+        zlib.push(chunks.peek().is_none() as u8);
+        let len = chunk.len() as u16;
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(chunk);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+// CRC-32 as used by PNG chunks (reflected, polynomial 0xEDB88320).
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// Adler-32 checksum at the end of a zlib stream.
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b could overflow
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Palette;
+
+    // Undo zlib_stored, checking the block headers and checksum along the way.
+    fn unzlib_stored(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(&zlib[..2], &[0x78, 0x01]);
+        let mut data = Vec::new();
+        let mut pos = 2;
+        loop {
+            let last = zlib[pos] & 1 == 1;
+            assert_eq!(zlib[pos] >> 1, 0);
+            let len = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]);
+            let nlen = u16::from_le_bytes([zlib[pos + 3], zlib[pos + 4]]);
+            assert_eq!(len, !nlen);
+            data.extend_from_slice(&zlib[pos + 5..pos + 5 + len as usize]);
+            pos += 5 + len as usize;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(&zlib[pos..], &adler32(&data).to_be_bytes());
+        data
+    }
+
+    // The chunks of a png as (type, data), checking every CRC.
+    fn png_chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let body = &png[pos + 4..pos + 8 + len];
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(body), crc);
+            chunks.push((String::from_utf8(body[..4].to_vec()).unwrap(), body[4..].to_vec()));
+            pos += 12 + len;
+        }
+        chunks
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(&[]), 1);
+        // long enough that the sums have to be reduced along the way
+        let data = vec![0xFF; 100_000];
+        let (a, b) = data.iter().fold((1u64, 0u64), |(a, b), byte| {
+            let a = a + *byte as u64;
+            (a, b + a)
+        });
+        assert_eq!(adler32(&data), (((b % 65521) << 16) | (a % 65521)) as u32);
+    }
+
+    #[test]
+    fn stored_blocks() {
+        assert!(unzlib_stored(&zlib_stored(&[])).is_empty());
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7) as u8).collect();
+        let zlib = zlib_stored(&data);
+        assert_eq!(zlib.len(), 2 + 4 * 5 + data.len() + 4);
+        assert_eq!(unzlib_stored(&zlib), data);
+    }
+
+    #[test]
+    fn png_of_screen() {
+        let mut s = Screen::new(3, 2);
+        s.set_palette(Palette::amber());
+        s.set_pixel(1, 0);
+        s.set_pixel(2, 1);
+        let mut rgba = vec![0; 3 * 2 * 4];
+        s.render_into(&mut rgba);
+        let png = encode_png(&rgba, 3, 2);
+
+        let chunks = png_chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, vec![0, 0, 0, 3, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+        let raw = unzlib_stored(&chunks[1].1);
+        let (off, on) = (Palette::amber().colors[0], Palette::amber().colors[1]);
+        let mut expected = vec![0];
+        expected.extend([off, on, off].concat());
+        expected.push(0);
+        expected.extend([off, off, on].concat());
+        assert_eq!(raw, expected);
+    }
+
+    #[test]
+    fn ppm_and_scale() {
+        let rgba = [1, 2, 3, 0, 4, 5, 6, 0xFF];
+        assert_eq!(encode_ppm(&rgba, 2, 1), b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06".to_vec());
+
+        let scaled = scale_image(&rgba, 2, 1, 2);
+        let (a, b) = ([1, 2, 3, 0], [4, 5, 6, 0xFF]);
+        assert_eq!(scaled, [a, a, b, b, a, a, b, b].concat());
+        assert_eq!(scale_image(&rgba, 2, 1, 1), rgba.to_vec());
+    }
+
+    #[test]
+    fn save_to_file() {
+        let mut s = Screen::new(4, 2);
+        s.set_pixel(0, 0);
+        let dir = std::env::temp_dir();
+        let png_path = dir.join(format!("chip8-screenshot-test-{}.png", std::process::id()));
+        let ppm_path = dir.join(format!("chip8-screenshot-test-{}.ppm", std::process::id()));
+
+        save_screenshot(&s, &png_path, 3).unwrap();
+        let png = fs::read(&png_path).unwrap();
+        assert_eq!(png_chunks(&png)[0].1[..8], [0, 0, 0, 12, 0, 0, 0, 6]);
+        save_screenshot(&s, &ppm_path, 1).unwrap();
+        let ppm = fs::read(&ppm_path).unwrap();
+        assert_eq!(ppm.len(), b"P6\n4 2\n255\n".len() + 4 * 2 * 3);
+        assert_eq!(ppm[11..14], [0xFF, 0xFF, 0xFF]);
+        let _ = fs::remove_file(&png_path);
+        let _ = fs::remove_file(&ppm_path);
+
+        assert!(matches!(
+            save_screenshot(&s, Path::new("screen.bmp"), 1),
+            Err(ScreenshotError::UnknownFormat(_))
+        ));
+        assert!(matches!(save_screenshot(&s, Path::new("screen.png"), 0), Err(ScreenshotError::ZeroScale)));
+    }
+}